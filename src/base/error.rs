@@ -0,0 +1,37 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use thiserror::Error as ThisError;
+
+// Error kinds of the engine, all the functions still return `anyhow::Result`,
+// callers can use `downcast_ref::<Error>()` to tell the kind of an error.
+#[derive(ThisError, Debug)]
+pub enum Error {
+    #[error("transaction error: {0}")]
+    Txn(String),
+
+    // data read back from a file fails the checksum or can not be parsed
+    #[error("corruption: {0}")]
+    Corruption(String),
+}
+
+impl Error {
+    pub fn txn_error(msg: &str) -> anyhow::Error {
+        Error::Txn(msg.to_string()).into()
+    }
+
+    pub fn corruption_error(msg: &str) -> anyhow::Error {
+        Error::Corruption(msg.to_string()).into()
+    }
+}
@@ -55,7 +55,7 @@ impl KeyBytes {
         Self { key, version }
     }
 
-    pub fn to_key_slice(&self) -> KeySlice {
+    pub fn to_key_slice(&self) -> KeySlice<'_> {
         KeySlice {
             key: self.key.as_ref(),
             version: self.version,
@@ -78,11 +78,15 @@ impl KeyBytes {
 }
 
 impl<'a> KeySlice<'a> {
-    pub fn to_key_bytes(&self) -> KeyBytes {
+    pub fn from_slice(key: &'a [u8], version: Version) -> Self {
+        Self { key, version }
+    }
+
+    pub fn to_key_bytes(self) -> KeyBytes {
         KeyBytes::new(self.key.to_vec().into(), self.version)
     }
 
-    pub fn to_key_vec(&self) -> KeyVec {
+    pub fn to_key_vec(self) -> KeyVec {
         KeyVec {
             key: self.key.to_vec(),
             version: self.version,
@@ -98,13 +102,17 @@ impl KeyVec {
         }
     }
 
-    pub fn to_key_slice(&self) -> KeySlice {
+    pub fn to_key_slice(&self) -> KeySlice<'_> {
         KeySlice {
             key: self.key.as_ref(),
             version: self.version,
         }
     }
 
+    pub fn from_vec(key: Vec<u8>, version: Version) -> Self {
+        Self { key, version }
+    }
+
     pub fn from_key_slice(slice: &KeySlice) -> Self {
         Self {
             key: slice.key.to_vec(),
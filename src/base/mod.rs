@@ -12,8 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod error;
 mod key;
 
+pub use error::Error;
 pub use key::KeyBytes;
 pub use key::KeySlice;
 pub use key::KeyVec;
@@ -0,0 +1,34 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Runs a compaction job out of the engine process. The job is read in json from
+// stdin, the output sstables are written next to the inputs and their metadata
+// is written in json to stdout, see `LsmOptions::compaction_worker`. It has no
+// table properties collectors, an engine with collectors needs a worker built
+// with the same factories passed to `run_compaction_job`.
+
+use std::io::Write;
+
+use anyhow::Result;
+use lsm_engine::compact::CompactionJob;
+use lsm_engine::compact::run_compaction_job;
+
+fn main() -> Result<()> {
+    let job: CompactionJob = serde_json::from_reader(std::io::stdin().lock())?;
+    let outputs = run_compaction_job(&job, &[])?;
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &outputs)?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -18,7 +18,7 @@ pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct Block {
-    pub(crate) data: Vec<u8>,
+    pub(crate) data: Bytes,
     pub(crate) offsets: Vec<u16>,
 }
 
@@ -28,7 +28,7 @@ impl Block {
     // offset array(u16 per element)
     // number of elements(u16)
     pub fn encode(&self) -> Bytes {
-        let mut buf = self.data.clone();
+        let mut buf = self.data.to_vec();
         for offset in &self.offsets {
             buf.put_u16(*offset);
         }
@@ -39,6 +39,11 @@ impl Block {
     }
 
     pub fn decode(data: &[u8]) -> Self {
+        Self::decode_from_bytes(Bytes::copy_from_slice(data))
+    }
+
+    // decode without copying, the entries of block share the memory with `data`
+    pub fn decode_from_bytes(data: Bytes) -> Self {
         let number_of_entry = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
         let data_end = data.len() - SIZEOF_U16 - number_of_entry * SIZEOF_U16;
         let offfsets_raw = &data[data_end..data.len() - SIZEOF_U16];
@@ -46,7 +51,7 @@ impl Block {
             .chunks(SIZEOF_U16)
             .map(|mut x| x.get_u16())
             .collect();
-        let data = data[0..data_end].to_vec();
+        let data = data.slice(0..data_end);
 
         Self { data, offsets }
     }
@@ -103,7 +103,7 @@ impl BlockBuilder {
     pub fn finalize(self) -> Block {
         assert!(!self.is_empty(), "block MUST not be empty");
         Block {
-            data: self.data,
+            data: self.data.into(),
             offsets: self.offsets,
         }
     }
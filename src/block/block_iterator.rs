@@ -0,0 +1,173 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Buf;
+
+use super::Block;
+use super::block::SIZEOF_U16;
+use crate::base::KeySlice;
+use crate::base::KeyVec;
+
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+pub struct BlockIterator {
+    block: Arc<Block>,
+
+    // current key, empty means the iterator is invalid
+    key: KeyVec,
+
+    // range of the current value in block data
+    value_range: (usize, usize),
+
+    // index of the current key-value pair
+    idx: usize,
+
+    // keys in the block are prefix-compressed against the first key
+    first_key: KeyVec,
+}
+
+impl BlockIterator {
+    fn new(block: Arc<Block>) -> Self {
+        let mut iter = Self {
+            block,
+            key: KeyVec::new(),
+            value_range: (0, 0),
+            idx: 0,
+            first_key: KeyVec::new(),
+        };
+        if !iter.block.offsets.is_empty() {
+            iter.seek_to(0);
+            iter.first_key = iter.key.clone();
+        }
+        iter
+    }
+
+    pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
+        Self::new(block)
+    }
+
+    pub fn create_and_seek_to_key(block: Arc<Block>, key: KeySlice) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_key(key);
+        iter
+    }
+
+    pub fn key(&self) -> KeySlice<'_> {
+        self.key.to_key_slice()
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.block.data[self.value_range.0..self.value_range.1]
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.key.is_empty()
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.seek_to(0);
+    }
+
+    pub fn next(&mut self) {
+        self.seek_to(self.idx + 1);
+    }
+
+    // seek to the first key >= `key`
+    pub fn seek_to_key(&mut self, key: KeySlice) {
+        let mut low = 0;
+        let mut high = self.block.offsets.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.seek_to(mid);
+            if self.key() < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        self.seek_to(low);
+    }
+
+    // decode the key-value pair at index `idx`, see `BlockBuilder::add` for the encoding format
+    fn seek_to(&mut self, idx: usize) {
+        self.idx = idx;
+        if idx >= self.block.offsets.len() {
+            self.key = KeyVec::new();
+            self.value_range = (0, 0);
+            return;
+        }
+
+        let offset = self.block.offsets[idx] as usize;
+        let mut entry = &self.block.data[offset..];
+        let overlap_index = entry.get_u16() as usize;
+        let rest_len = entry.get_u16() as usize;
+        let mut key = Vec::with_capacity(overlap_index + rest_len);
+        key.extend_from_slice(&self.first_key.key_ref()[..overlap_index]);
+        key.extend_from_slice(&entry[..rest_len]);
+        entry.advance(rest_len);
+        let version = entry.get_u64();
+        self.key = KeyVec::from_vec(key, version);
+
+        let value_len = entry.get_u16() as usize;
+        let value_begin = offset + SIZEOF_U16 * 3 + rest_len + SIZEOF_U64;
+        self.value_range = (value_begin, value_begin + value_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use super::BlockIterator;
+    use crate::base::KeyBytes;
+    use crate::block::BlockBuilder;
+
+    fn key_of(i: usize) -> KeyBytes {
+        KeyBytes::new(Bytes::from(format!("key_{:03}", i)), i as u64)
+    }
+
+    #[test]
+    fn test_block_iterator() {
+        let mut builder = BlockBuilder::new(4096);
+        for i in 0..100 {
+            let value = format!("value_{}", i);
+            assert!(builder.add(key_of(i).to_key_slice(), value.as_bytes()));
+        }
+        let block = Arc::new(builder.finalize());
+
+        let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+        for i in 0..100 {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(i).to_key_slice());
+            assert_eq!(iter.value(), format!("value_{}", i).as_bytes());
+            iter.next();
+        }
+        assert!(!iter.is_valid());
+
+        let iter = BlockIterator::create_and_seek_to_key(block.clone(), key_of(42).to_key_slice());
+        assert_eq!(iter.key(), key_of(42).to_key_slice());
+
+        // seek to a key between two keys in the block
+        let key = KeyBytes::new(Bytes::from("key_042"), 43);
+        let iter = BlockIterator::create_and_seek_to_key(block.clone(), key.to_key_slice());
+        assert_eq!(iter.key(), key_of(43).to_key_slice());
+
+        let iter = BlockIterator::create_and_seek_to_key(block, key_of(100).to_key_slice());
+        assert!(!iter.is_valid());
+    }
+}
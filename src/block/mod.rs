@@ -15,6 +15,8 @@
 #[allow(clippy::module_inception)]
 mod block;
 mod block_builder;
+mod block_iterator;
 
 pub use block::Block;
 pub use block_builder::BlockBuilder;
+pub use block_iterator::BlockIterator;
@@ -12,7 +12,225 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use serde::Serialize;
 
-#[derive(Serialize, Deserialize)]
-pub enum CompactionTask {}
+use super::FifoCompactionController;
+use super::FifoCompactionOptions;
+use super::LeveledCompactionController;
+use super::LeveledCompactionOptions;
+use super::TieredCompactionController;
+use super::TieredCompactionOptions;
+use crate::base::Version;
+use crate::engine::LsmEngineState;
+use crate::table::SsTableId;
+
+#[derive(Clone, Debug, Default)]
+pub enum CompactionOptions {
+    // sstables are only compacted by `LsmEngine::force_full_compaction`
+    #[default]
+    NoCompaction,
+    Leveled(LeveledCompactionOptions),
+    Tiered(TieredCompactionOptions),
+    Fifo(FifoCompactionOptions),
+}
+
+// Progress of `LsmEngine::compact_range`, reported once for every level, or only
+// once for level 0 with tiered compaction, which merges all the levels at once
+pub struct CompactRangeProgress {
+    // sstables of this level overlapping the range were compacted into the next level
+    pub level: usize,
+    pub num_levels: usize,
+    // number of sstables compacted, 0 if none of this level overlaps the range
+    pub input_sstables: usize,
+}
+
+pub type CompactRangeProgressCallback = Box<dyn Fn(&CompactRangeProgress) + Send + Sync>;
+
+pub struct CompactRangeOptions {
+    // automatic compactions wait until the whole range is compacted, otherwise
+    // they may run between the levels of the range compaction
+    pub exclusive_manual_compaction: bool,
+
+    // called after each level is compacted
+    pub progress: Option<CompactRangeProgressCallback>,
+}
+
+impl Default for CompactRangeOptions {
+    fn default() -> Self {
+        Self {
+            exclusive_manual_compaction: true,
+            progress: None,
+        }
+    }
+}
+
+// Compact sstables of the upper level and the overlapped sstables
+// of the lower level into the lower level
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct LeveledCompactionTask {
+    // 0 means level 0, whose sstables MUST be the oldest ones in level 0.
+    // the same as `lower_level` if the sstables are rewritten in the bottom level.
+    pub upper_level: usize,
+    pub upper_level_sstables: Vec<SsTableId>,
+    pub lower_level: usize,
+    pub lower_level_sstables: Vec<SsTableId>,
+    pub is_lower_level_bottom: bool,
+}
+
+// Merge the newer sorted runs, a sorted run is a level 0 sstable or a level
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TieredCompactionTask {
+    pub l0_sstables: Vec<SsTableId>,
+    // (level, all the sstables of the level)
+    pub levels: Vec<(usize, Vec<SsTableId>)>,
+    pub output_level: usize,
+    // true if the oldest sorted run is merged
+    pub is_output_bottom: bool,
+}
+
+// Drop the sstables without writing any output
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FifoCompactionTask {
+    pub sstables: Vec<SsTableId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum CompactionTask {
+    Leveled(LeveledCompactionTask),
+    Tiered(TieredCompactionTask),
+    Fifo(FifoCompactionTask),
+    // compact all the sstables into the bottom level
+    Full {
+        l0_sstables: Vec<SsTableId>,
+        // (level, sstables) of level 1 to the bottom level
+        levels: Vec<(usize, Vec<SsTableId>)>,
+    },
+}
+
+impl CompactionTask {
+    // input sstables, the ones with newer data come first
+    pub fn input_sstables(&self) -> Vec<SsTableId> {
+        match self {
+            CompactionTask::Leveled(task) => task
+                .upper_level_sstables
+                .iter()
+                .chain(task.lower_level_sstables.iter())
+                .copied()
+                .collect(),
+            CompactionTask::Fifo(task) => task.sstables.clone(),
+            CompactionTask::Tiered(TieredCompactionTask {
+                l0_sstables,
+                levels,
+                ..
+            })
+            | CompactionTask::Full {
+                l0_sstables,
+                levels,
+            } => l0_sstables
+                .iter()
+                .chain(levels.iter().flat_map(|(_, ids)| ids.iter()))
+                .copied()
+                .collect(),
+        }
+    }
+
+    // return true if the input sstables are dropped without merging them
+    pub fn is_drop(&self) -> bool {
+        matches!(self, CompactionTask::Fifo(_))
+    }
+
+    // return true if the input sstables can be moved into the output level without
+    // being rewritten, which is when they overlap neither each other nor any sstable
+    // of the output level
+    pub fn is_trivial_move(&self, state: &LsmEngineState) -> bool {
+        let CompactionTask::Leveled(task) = self else {
+            return false;
+        };
+        if !task.lower_level_sstables.is_empty()
+            || task.upper_level_sstables.is_empty()
+            || task.upper_level == task.lower_level
+        {
+            return false;
+        }
+        let mut ranges: Vec<(&[u8], &[u8])> = task
+            .upper_level_sstables
+            .iter()
+            .map(|id| {
+                let info = &state.sstables[id];
+                (info.first_key().key_ref(), info.last_key().key_ref())
+            })
+            .collect();
+        ranges.sort();
+        ranges.windows(2).all(|pair| pair[0].1 < pair[1].0)
+            && ranges
+                .iter()
+                .all(|(first, last)| !state.level_overlaps(task.lower_level, first, last))
+    }
+
+    // level the output sstables are put into
+    pub fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Tiered(task) => task.output_level,
+            CompactionTask::Fifo(_) => 0,
+            CompactionTask::Full { levels, .. } => levels.last().map_or(0, |(level, _)| *level),
+        }
+    }
+
+    // return true if the output is put into the bottom level, which has the oldest data
+    pub fn is_output_bottom(&self) -> bool {
+        match self {
+            CompactionTask::Leveled(task) => task.is_lower_level_bottom,
+            CompactionTask::Tiered(task) => task.is_output_bottom,
+            CompactionTask::Fifo(_) => false,
+            CompactionTask::Full { .. } => true,
+        }
+    }
+}
+
+// Picks compaction tasks by the configured strategy
+pub enum CompactionController {
+    NoCompaction,
+    Leveled(LeveledCompactionController),
+    Tiered(TieredCompactionController),
+    Fifo(FifoCompactionController),
+}
+
+impl CompactionController {
+    pub fn new(options: &CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Fifo(options) => {
+                CompactionController::Fifo(FifoCompactionController::new(options.clone()))
+            }
+        }
+    }
+
+    // return true if compaction tasks are picked in background
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, CompactionController::NoCompaction)
+    }
+
+    // versions above `watermark` may be read by the readers
+    pub fn generate_compaction_task(
+        &self,
+        state: &LsmEngineState,
+        watermark: Version,
+    ) -> Option<CompactionTask> {
+        match self {
+            CompactionController::NoCompaction => None,
+            CompactionController::Leveled(controller) => {
+                controller.generate_compaction_task(state, watermark)
+            }
+            CompactionController::Tiered(controller) => controller.generate_compaction_task(state),
+            CompactionController::Fifo(controller) => controller.generate_compaction_task(state),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::base::KeySlice;
+use crate::base::Version;
+use crate::iterator::StorageIterator;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CompactionDecision {
+    Keep,
+    // the value is replaced by a tombstone, so older versions of the key stay hidden
+    Remove,
+    ChangeValue(Vec<u8>),
+}
+
+// Decides what to do with the key-value pairs visited by compaction, e.g. to
+// drop expired data. Only versions not above `MvccInner::watermark()` are passed
+// to the filter, so versions written after the oldest live snapshot are never
+// changed. Tombstones are not passed to the filter.
+pub trait CompactionFilter: Send + Sync {
+    // `level` is the level the output of the compaction is put into
+    fn filter(
+        &self,
+        level: usize,
+        key: &[u8],
+        version: Version,
+        value: &[u8],
+    ) -> CompactionDecision;
+}
+
+// Applies the compaction filter to the key-value pairs of the inner iterator
+pub struct CompactionFilterIterator<I: StorageIterator> {
+    iter: I,
+    filter: Option<Arc<dyn CompactionFilter>>,
+    level: usize,
+    watermark: Version,
+    // decision of the current key
+    decision: CompactionDecision,
+}
+
+impl<I: StorageIterator> CompactionFilterIterator<I> {
+    pub fn create(
+        iter: I,
+        filter: Option<Arc<dyn CompactionFilter>>,
+        level: usize,
+        watermark: Version,
+    ) -> Self {
+        let mut iter = Self {
+            iter,
+            filter,
+            level,
+            watermark,
+            decision: CompactionDecision::Keep,
+        };
+        iter.decide();
+        iter
+    }
+
+    fn decide(&mut self) {
+        self.decision = CompactionDecision::Keep;
+        let Some(filter) = &self.filter else {
+            return;
+        };
+        if !self.iter.is_valid() {
+            return;
+        }
+        let key = self.iter.key();
+        if key.version() > self.watermark || self.iter.value().is_empty() {
+            return;
+        }
+        self.decision = filter.filter(self.level, key.key_ref(), key.version(), self.iter.value());
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for CompactionFilterIterator<I> {
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        match &self.decision {
+            CompactionDecision::Keep => self.iter.value(),
+            CompactionDecision::Remove => &[],
+            CompactionDecision::ChangeValue(value) => value,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()?;
+        self.decide();
+        Ok(())
+    }
+}
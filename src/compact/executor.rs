@@ -0,0 +1,243 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::bail;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::CompactionTask;
+use super::GcIterator;
+use crate::base::Version;
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::PosixFileSystem;
+use crate::fs::RateLimiter;
+use crate::iterator::MergeIterator;
+use crate::iterator::StorageIterator;
+use crate::table::FileObject;
+use crate::table::SsTable;
+use crate::table::SsTableBuilder;
+use crate::table::SsTableId;
+use crate::table::SsTableIterator;
+use crate::table::TablePropertiesCollectorFactory;
+
+// Write the merged data before `end` into sstables cut at `target_file_size`.
+// Versions of a key are never cut into different sstables, since only one
+// sstable of each level is searched when reading the key.
+pub fn write_compaction_outputs<T>(
+    iter: &mut impl StorageIterator,
+    end: Option<&[u8]>,
+    target_file_size: usize,
+    mut new_builder: impl FnMut() -> Result<SsTableBuilder>,
+    mut build: impl FnMut(SsTableBuilder) -> Result<T>,
+    outputs: &mut Vec<T>,
+) -> Result<()> {
+    let mut builder = new_builder()?;
+    let mut last_key = Vec::new();
+    while iter.is_valid() && end.is_none_or(|end| iter.key().key_ref() < end) {
+        if builder.estimated_size() >= target_file_size && iter.key().key_ref() != last_key {
+            let full = std::mem::replace(&mut builder, new_builder()?);
+            outputs.push(build(full)?);
+        }
+        last_key.clear();
+        last_key.extend_from_slice(iter.key().key_ref());
+        builder.add(iter.key(), iter.value())?;
+        iter.next()?;
+    }
+    if !builder.is_empty() {
+        outputs.push(build(builder)?);
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompactionJobInput {
+    pub path: PathBuf,
+    // see `SsTable::global_version`
+    pub global_version: Option<Version>,
+}
+
+// A compaction task with everything needed to run it out of the engine,
+// the input sstables are only read and the output is left in `output_dir`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompactionJob {
+    // unique in `output_dir`, output files are named after it
+    pub job_id: u64,
+    pub task: CompactionTask,
+    // in the order of `CompactionTask::input_sstables`
+    pub inputs: Vec<CompactionJobInput>,
+    pub output_dir: PathBuf,
+    // see `GcIterator`
+    pub watermark: Version,
+
+    pub block_size: usize,
+    pub index_partition_size: usize,
+    pub target_file_size: usize,
+    pub readahead_blocks: usize,
+    // read the inputs and write the outputs with direct I/O
+    pub use_direct_io: bool,
+    // the outputs are written at this rate, the rate of `LsmOptions::rate_limiter`
+    // when the job is created. None means the writes are not throttled.
+    pub rate_bytes_per_sec: Option<usize>,
+    // names of `LsmOptions::table_properties_collector_factories`
+    pub table_properties_collectors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct CompactionJobOutput {
+    pub path: PathBuf,
+    pub first_key: Vec<u8>,
+    pub last_key: Vec<u8>,
+    pub table_size: usize,
+}
+
+// Run the job in this process with the files on local disk. The collectors of the
+// outputs are created by the factories with the names in the job, it fails if
+// one of them is not in `factories`.
+pub fn run_compaction_job(
+    job: &CompactionJob,
+    factories: &[Arc<dyn TablePropertiesCollectorFactory>],
+) -> Result<Vec<CompactionJobOutput>> {
+    let factories = job
+        .table_properties_collectors
+        .iter()
+        .map(|name| {
+            let factory = factories.iter().find(|factory| factory.name() == name);
+            factory.cloned().with_context(|| {
+                format!(
+                    "table properties collector {:?} of compaction job {} is not found",
+                    name, job.job_id
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let rate_limiter = job
+        .rate_bytes_per_sec
+        .map(|rate_bytes_per_sec| Arc::new(RateLimiter::new(rate_bytes_per_sec)));
+    let fs = Arc::new(PosixFileSystem);
+    let file_options = FileOptions {
+        use_mmap: false,
+        use_direct_reads: job.use_direct_io,
+        use_direct_writes: job.use_direct_io,
+    };
+    let mut iters = Vec::new();
+    for (idx, input) in job.inputs.iter().enumerate() {
+        let file = FileObject::open(fs.as_ref(), &input.path, file_options)?;
+        let mut table = SsTable::open(idx as SsTableId, None, file)
+            .with_context(|| format!("open sstable {:?}", input.path))?;
+        if let Some(global_version) = input.global_version {
+            table.set_global_version(global_version);
+        }
+        let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(table))?;
+        iter.set_readahead(job.readahead_blocks);
+        iters.push(iter);
+    }
+    let mut iter = GcIterator::create(
+        MergeIterator::create(iters),
+        job.watermark,
+        job.task.is_output_bottom(),
+    )?;
+
+    // a level 0 sstable is a sorted run by itself, so level 0 output is not cut
+    let target_file_size = if job.task.output_level() == 0 {
+        usize::MAX
+    } else {
+        job.target_file_size
+    };
+    let new_builder = || -> Result<SsTableBuilder> {
+        let mut builder = SsTableBuilder::create(job.block_size)?;
+        builder.set_index_partition_size(job.index_partition_size);
+        builder.set_file_system(fs.clone());
+        builder.set_file_options(file_options);
+        builder.set_rate_limiter(rate_limiter.clone());
+        for factory in &factories {
+            builder.add_properties_collector(factory.create());
+        }
+        Ok(builder)
+    };
+    let mut num_outputs = 0;
+    let build = |builder: SsTableBuilder| -> Result<CompactionJobOutput> {
+        let idx = num_outputs;
+        num_outputs += 1;
+        let path = job
+            .output_dir
+            .join(format!("job_{:05}_{}.sst", job.job_id, idx));
+        let table = builder.build(idx as SsTableId, None, &path)?;
+        Ok(CompactionJobOutput {
+            path,
+            first_key: table.first_key().key_ref().to_vec(),
+            last_key: table.last_key().key_ref().to_vec(),
+            table_size: table.table_size(),
+        })
+    };
+
+    let mut outputs = Vec::new();
+    let result = write_compaction_outputs(
+        &mut iter,
+        None,
+        target_file_size,
+        new_builder,
+        build,
+        &mut outputs,
+    );
+    if let Err(err) = result {
+        for output in outputs {
+            let _ = fs.remove(&output.path);
+        }
+        return Err(err);
+    }
+    Ok(outputs)
+}
+
+// Run the job in a `compaction_worker` process, which reads the job in json from
+// stdin and writes the outputs in json to stdout
+pub fn run_compaction_worker(
+    worker: &Path,
+    job: &CompactionJob,
+) -> Result<Vec<CompactionJobOutput>> {
+    let mut child = Command::new(worker)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("spawn compaction worker {:?}", worker))?;
+    // safe to unwrap, stdin is piped
+    let mut stdin = child.stdin.take().unwrap();
+    let written = serde_json::to_writer(&mut stdin, job)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(stdin.flush()?));
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "compaction job {} failed with {}: {}",
+            job.job_id,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    written?;
+    serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("parse output of compaction job {}", job.job_id))
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use super::CompactionTask;
+use super::FifoCompactionTask;
+use crate::engine::LsmEngineState;
+use crate::table::SsTableId;
+
+#[derive(Clone, Debug)]
+pub struct FifoCompactionOptions {
+    // the oldest sstables are dropped when the total size in bytes exceeds this
+    pub max_table_files_size: usize,
+
+    // sstables created more than this many seconds ago are dropped, 0 means no limit
+    pub ttl: u64,
+}
+
+impl Default for FifoCompactionOptions {
+    fn default() -> Self {
+        Self {
+            max_table_files_size: 1 << 30,
+            ttl: 0,
+        }
+    }
+}
+
+// FIFO compaction never merges sstables, it only drops the oldest ones,
+// for data that is only useful for a while, such as logs.
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn generate_compaction_task(&self, state: &LsmEngineState) -> Option<CompactionTask> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        self.generate_compaction_task_at(state, now)
+    }
+
+    // `now` is seconds since unix epoch
+    pub fn generate_compaction_task_at(
+        &self,
+        state: &LsmEngineState,
+        now: u64,
+    ) -> Option<CompactionTask> {
+        let sstables = Self::sstables_from_oldest(state);
+        let mut total_size: usize = sstables
+            .iter()
+            .map(|id| state.sstables[id].table_size())
+            .sum();
+
+        // drop from the oldest data, so that a dropped key never exposes an older version
+        let mut dropped = Vec::new();
+        for id in sstables {
+            let info = &state.sstables[&id];
+            let expired =
+                self.options.ttl > 0 && info.properties().creation_time + self.options.ttl <= now;
+            if total_size <= self.options.max_table_files_size && !expired {
+                break;
+            }
+            total_size -= info.table_size();
+            dropped.push(id);
+        }
+        if dropped.is_empty() {
+            return None;
+        }
+        Some(CompactionTask::Fifo(FifoCompactionTask {
+            sstables: dropped,
+        }))
+    }
+
+    // the level does not tell the age of an sstable, an ingested sstable may be put
+    // into the last level with newer data than the upper levels. so sstables of all
+    // the levels are ordered by the newest version in them, then by creation time.
+    fn sstables_from_oldest(state: &LsmEngineState) -> Vec<SsTableId> {
+        let mut sstables: Vec<SsTableId> = state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, ids)| ids))
+            .copied()
+            .collect();
+        sstables.sort_by_key(|id| {
+            let info = &state.sstables[id];
+            (
+                info.global_version.unwrap_or(info.max_version),
+                info.properties().creation_time,
+                *id,
+            )
+        });
+        sstables
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+
+use crate::base::KeySlice;
+use crate::base::KeyVec;
+use crate::base::Version;
+use crate::iterator::StorageIterator;
+
+// Drops the versions no snapshot can read during compaction. Readers never read
+// below the watermark, so of the versions of a key at or below the watermark only
+// the newest one is visible. If it is a tombstone in the bottom level, there is
+// no older data for it to hide, so it is dropped too.
+pub struct GcIterator<I: StorageIterator> {
+    iter: I,
+    watermark: Version,
+    drop_tombstones: bool,
+    // the current key-value pair, taken from `iter` which is one step ahead
+    key: KeyVec,
+    value: Vec<u8>,
+    valid: bool,
+}
+
+impl<I: StorageIterator> GcIterator<I> {
+    pub fn create(iter: I, watermark: Version, drop_tombstones: bool) -> Result<Self> {
+        let mut iter = Self {
+            iter,
+            watermark,
+            drop_tombstones,
+            key: KeyVec::new(),
+            value: Vec::new(),
+            valid: false,
+        };
+        iter.advance()?;
+        Ok(iter)
+    }
+
+    // move to the next key-value pair which is not garbage
+    fn advance(&mut self) -> Result<()> {
+        loop {
+            if !self.iter.is_valid() {
+                self.valid = false;
+                return Ok(());
+            }
+            self.key = KeyVec::from_key_slice(&self.iter.key());
+            self.value.clear();
+            self.value.extend_from_slice(self.iter.value());
+            self.iter.next()?;
+
+            if self.key.version() > self.watermark {
+                break;
+            }
+            // versions are in ascending order, a newer version at or below the
+            // watermark hides this one from all the readers
+            let hidden = self.iter.is_valid()
+                && self.iter.key().key_ref() == self.key.key_ref()
+                && self.iter.key().version() <= self.watermark;
+            if hidden || (self.drop_tombstones && self.value.is_empty()) {
+                continue;
+            }
+            break;
+        }
+        self.valid = true;
+        Ok(())
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for GcIterator<I> {
+    fn key(&self) -> KeySlice<'_> {
+        self.key.to_key_slice()
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.advance()
+    }
+}
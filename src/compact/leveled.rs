@@ -0,0 +1,278 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use super::CompactionTask;
+use super::LeveledCompactionTask;
+use crate::base::Version;
+use crate::engine::LsmEngineState;
+use crate::table::SsTableId;
+
+#[derive(Clone, Debug)]
+pub struct LeveledCompactionOptions {
+    // level 0 is compacted when it has this many sstables
+    pub level0_file_num_compaction_trigger: usize,
+
+    // target size in bytes of level 1, or of the base level if
+    // `level_compaction_dynamic_level_bytes` is enabled
+    pub max_bytes_for_level_base: usize,
+
+    // target size of a level is this times of the upper level
+    pub max_bytes_for_level_multiplier: usize,
+
+    // compute the target sizes from the size of the last level instead of level 1.
+    // upper levels are left empty until the data grows, level 0 is compacted into
+    // the first non-empty level (the base level), so that most data is in the last level.
+    pub level_compaction_dynamic_level_bytes: bool,
+
+    // a sstable is compacted when this ratio of its entries are tombstones,
+    // 0 means disabled
+    pub tombstone_compaction_ratio: f64,
+
+    // a sstable is compacted when it was created more than this many seconds
+    // ago, so that old data in the bottom level is rewritten to drop the garbage
+    // versions. 0 means disabled.
+    pub periodic_compaction_seconds: u64,
+}
+
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            max_bytes_for_level_base: 256 << 20,
+            max_bytes_for_level_multiplier: 10,
+            level_compaction_dynamic_level_bytes: false,
+            tombstone_compaction_ratio: 0.0,
+            periodic_compaction_seconds: 0,
+        }
+    }
+}
+
+pub struct LeveledCompactionController {
+    options: LeveledCompactionOptions,
+}
+
+impl LeveledCompactionController {
+    pub fn new(options: LeveledCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    // return the base level and the target sizes of level 1 to the last level,
+    // levels above the base level have target size 0
+    pub fn target_level_sizes(&self, state: &LsmEngineState) -> (usize, Vec<usize>) {
+        let num_levels = state.levels.len();
+        let base_size = self.options.max_bytes_for_level_base;
+        let multiplier = self.options.max_bytes_for_level_multiplier;
+        let mut targets = vec![0; num_levels];
+        if !self.options.level_compaction_dynamic_level_bytes {
+            let mut size = base_size;
+            for target in targets.iter_mut() {
+                *target = size;
+                size = size.saturating_mul(multiplier);
+            }
+            return (1, targets);
+        }
+
+        targets[num_levels - 1] = std::cmp::max(state.level_size(num_levels), base_size);
+        let mut base_level = num_levels;
+        for level in (1..num_levels).rev() {
+            let lower_size = targets[level];
+            if lower_size > base_size {
+                targets[level - 1] = lower_size / multiplier;
+                base_level = level;
+            }
+        }
+        // level 0 can not skip a non-empty level, which has newer data than the lower levels
+        let first_non_empty_level = (1..=num_levels)
+            .find(|level| !state.level_sstables(*level).is_empty())
+            .unwrap_or(num_levels);
+        (std::cmp::min(base_level, first_non_empty_level), targets)
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        state: &LsmEngineState,
+        watermark: Version,
+    ) -> Option<CompactionTask> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        self.generate_compaction_task_at(state, watermark, now)
+    }
+
+    // `now` is seconds since unix epoch
+    pub fn generate_compaction_task_at(
+        &self,
+        state: &LsmEngineState,
+        watermark: Version,
+        now: u64,
+    ) -> Option<CompactionTask> {
+        self.generate_size_compaction_task(state)
+            .or_else(|| self.generate_seek_compaction_task(state))
+            .or_else(|| self.generate_marked_compaction_task(state, watermark, now))
+    }
+
+    fn generate_size_compaction_task(&self, state: &LsmEngineState) -> Option<CompactionTask> {
+        let (base_level, targets) = self.target_level_sizes(state);
+        let num_levels = state.levels.len();
+
+        if !state.l0_sstables.is_empty()
+            && state.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+        {
+            return Some(Self::task(state, 0, state.l0_sstables.clone(), base_level));
+        }
+
+        // compact the level exceeding its target size the most, except the last level
+        let mut picked = None;
+        let mut max_score = 1.0;
+        for level in 1..num_levels {
+            let size = state.level_size(level);
+            if size == 0 {
+                continue;
+            }
+            let score = if targets[level - 1] == 0 {
+                f64::INFINITY
+            } else {
+                size as f64 / targets[level - 1] as f64
+            };
+            if score > max_score {
+                max_score = score;
+                picked = Some(level);
+            }
+        }
+        let level = picked?;
+
+        // the oldest sstable is compacted first, ids always increase
+        let id = *state.level_sstables(level).iter().min()?;
+        Some(Self::task(state, level, vec![id], level + 1))
+    }
+
+    // compact a sstable which runs out of allowed seeks into the next level, the
+    // bottom level sstables are never charged. a level 0 sstable is compacted
+    // with the older ones in level 0.
+    fn generate_seek_compaction_task(&self, state: &LsmEngineState) -> Option<CompactionTask> {
+        let exhausted = |id: &SsTableId| state.sstables[id].allowed_seeks() <= 0;
+        if let Some(idx) = state.l0_sstables.iter().position(exhausted) {
+            let (base_level, _) = self.target_level_sizes(state);
+            let sstables = state.l0_sstables[idx..].to_vec();
+            return Some(Self::task(state, 0, sstables, base_level));
+        }
+        let num_levels = state.levels.len();
+        (1..num_levels).find_map(|level| {
+            let id = *state
+                .level_sstables(level)
+                .iter()
+                .find(|id| exhausted(id))?;
+            Some(Self::task(state, level, vec![id], level + 1))
+        })
+    }
+
+    // compact a sstable of level 1 or lower with too many tombstones, or the oldest
+    // one past the compaction period. it is compacted into the next level, or
+    // rewritten in place if it is in the bottom level.
+    fn generate_marked_compaction_task(
+        &self,
+        state: &LsmEngineState,
+        watermark: Version,
+        now: u64,
+    ) -> Option<CompactionTask> {
+        let num_levels = state.levels.len();
+        let sstables = || {
+            (1..=num_levels).flat_map(|level| {
+                state
+                    .level_sstables(level)
+                    .iter()
+                    .map(move |id| (level, &state.sstables[id]))
+            })
+        };
+
+        let mut picked = None;
+        if self.options.tombstone_compaction_ratio > 0.0 {
+            let mut max_ratio = self.options.tombstone_compaction_ratio;
+            for (level, info) in sstables() {
+                // tombstones in the bottom level are dropped only if no reader can
+                // see them, otherwise rewriting it changes nothing
+                if level == num_levels && info.max_version > watermark {
+                    continue;
+                }
+                let properties = info.properties();
+                if properties.num_entries == 0 {
+                    continue;
+                }
+                let ratio = properties.num_tombstones as f64 / properties.num_entries as f64;
+                if ratio >= max_ratio {
+                    max_ratio = ratio;
+                    picked = Some((level, info.id()));
+                }
+            }
+        }
+        if picked.is_none() && self.options.periodic_compaction_seconds > 0 {
+            picked = sstables()
+                .filter(|(_, info)| {
+                    info.properties().creation_time + self.options.periodic_compaction_seconds
+                        <= now
+                })
+                .min_by_key(|(_, info)| info.properties().creation_time)
+                .map(|(level, info)| (level, info.id()));
+        }
+
+        let (level, id) = picked?;
+        if level < num_levels {
+            return Some(Self::task(state, level, vec![id], level + 1));
+        }
+        Some(CompactionTask::Leveled(LeveledCompactionTask {
+            upper_level: level,
+            upper_level_sstables: vec![id],
+            lower_level: level,
+            lower_level_sstables: Vec::new(),
+            is_lower_level_bottom: true,
+        }))
+    }
+
+    // compact the sstables of the upper level and the overlapped ones of the lower level
+    pub(crate) fn task(
+        state: &LsmEngineState,
+        upper_level: usize,
+        upper_level_sstables: Vec<SsTableId>,
+        lower_level: usize,
+    ) -> CompactionTask {
+        let first = upper_level_sstables
+            .iter()
+            .map(|id| state.sstables[id].first_key().key_ref())
+            .min()
+            .unwrap_or_default();
+        let last = upper_level_sstables
+            .iter()
+            .map(|id| state.sstables[id].last_key().key_ref())
+            .max()
+            .unwrap_or_default();
+        let lower_level_sstables = state
+            .level_sstables(lower_level)
+            .iter()
+            .filter(|id| state.sstables[*id].overlaps_key_range(first, last))
+            .copied()
+            .collect();
+
+        CompactionTask::Leveled(LeveledCompactionTask {
+            upper_level,
+            upper_level_sstables,
+            lower_level,
+            lower_level_sstables,
+            is_lower_level_bottom: lower_level == state.levels.len(),
+        })
+    }
+}
@@ -14,5 +14,35 @@
 
 #[allow(clippy::module_inception)]
 mod compact;
+mod compaction_filter;
+mod executor;
+mod fifo;
+mod gc_iterator;
+mod leveled;
+mod tiered;
 
+pub use compact::CompactRangeOptions;
+pub use compact::CompactRangeProgress;
+pub use compact::CompactRangeProgressCallback;
+pub use compact::CompactionController;
+pub use compact::CompactionOptions;
 pub use compact::CompactionTask;
+pub use compact::FifoCompactionTask;
+pub use compact::LeveledCompactionTask;
+pub use compact::TieredCompactionTask;
+pub use compaction_filter::CompactionDecision;
+pub use compaction_filter::CompactionFilter;
+pub use compaction_filter::CompactionFilterIterator;
+pub use executor::CompactionJob;
+pub use executor::CompactionJobInput;
+pub use executor::CompactionJobOutput;
+pub use executor::run_compaction_job;
+pub use executor::run_compaction_worker;
+pub use executor::write_compaction_outputs;
+pub use fifo::FifoCompactionController;
+pub use fifo::FifoCompactionOptions;
+pub use gc_iterator::GcIterator;
+pub use leveled::LeveledCompactionController;
+pub use leveled::LeveledCompactionOptions;
+pub use tiered::TieredCompactionController;
+pub use tiered::TieredCompactionOptions;
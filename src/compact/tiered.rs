@@ -0,0 +1,166 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::CompactionTask;
+use super::TieredCompactionTask;
+use crate::engine::LsmEngineState;
+use crate::table::SsTableId;
+
+#[derive(Clone, Debug)]
+pub struct TieredCompactionOptions {
+    // compaction is triggered when the number of sorted runs reaches this
+    pub level0_file_num_compaction_trigger: usize,
+
+    // a run is merged with the newer runs if its size is at most
+    // (100 + size_ratio) percent of their total size
+    pub size_ratio: usize,
+
+    // min and max number of sorted runs merged by a size ratio compaction
+    pub min_merge_width: usize,
+    pub max_merge_width: usize,
+
+    // all the runs are merged when the size of the runs except the oldest one
+    // exceeds this percent of the size of the oldest one
+    pub max_size_amplification_percent: usize,
+}
+
+impl Default for TieredCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: usize::MAX,
+            max_size_amplification_percent: 200,
+        }
+    }
+}
+
+// A sorted run is a level 0 sstable or a non-empty level
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SortedRun {
+    L0(SsTableId),
+    Level(usize),
+}
+
+// Size-tiered (universal) compaction, which merges the newer sorted runs of
+// similar size. It writes less than leveled compaction but reads more runs.
+pub struct TieredCompactionController {
+    options: TieredCompactionOptions,
+}
+
+impl TieredCompactionController {
+    pub fn new(options: TieredCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn generate_compaction_task(&self, state: &LsmEngineState) -> Option<CompactionTask> {
+        let runs = Self::sorted_runs(state);
+        let trigger = std::cmp::max(self.options.level0_file_num_compaction_trigger, 2);
+        if runs.len() < trigger {
+            return None;
+        }
+
+        // bound space amplification by merging all the runs
+        let (_, oldest_size) = runs[runs.len() - 1];
+        let newer_size: usize = runs[..runs.len() - 1].iter().map(|(_, size)| size).sum();
+        if newer_size * 100 > oldest_size * self.options.max_size_amplification_percent {
+            return Some(Self::task(state, &runs, 0, runs.len()));
+        }
+
+        // merge runs of similar size, starting from the newest ones
+        let max_width = std::cmp::max(self.options.max_merge_width, 2);
+        for start in 0..runs.len() {
+            let mut total = runs[start].1;
+            let mut end = start + 1;
+            while end < runs.len() && end - start < max_width {
+                if runs[end].1 * 100 > total * (100 + self.options.size_ratio) {
+                    break;
+                }
+                total += runs[end].1;
+                end += 1;
+            }
+            if end - start >= std::cmp::max(self.options.min_merge_width, 2) {
+                return Some(Self::task(state, &runs, start, end));
+            }
+        }
+
+        // reduce the number of sorted runs below the trigger
+        Some(Self::task(state, &runs, 0, runs.len() - trigger + 2))
+    }
+
+    // merge the sorted runs from the newest one with a sstable `overlaps` accepts
+    // down to the oldest one, used by the manual compaction of a key range
+    pub fn range_task(
+        state: &LsmEngineState,
+        overlaps: impl Fn(&SsTableId) -> bool,
+    ) -> Option<CompactionTask> {
+        let runs = Self::sorted_runs(state);
+        let start = runs.iter().position(|(run, _)| match run {
+            SortedRun::L0(id) => overlaps(id),
+            SortedRun::Level(level) => state.level_sstables(*level).iter().any(&overlaps),
+        })?;
+        Some(Self::task(state, &runs, start, runs.len()))
+    }
+
+    // sorted runs from the newest to the oldest, with their sizes
+    fn sorted_runs(state: &LsmEngineState) -> Vec<(SortedRun, usize)> {
+        state
+            .l0_sstables
+            .iter()
+            .map(|id| (SortedRun::L0(*id), state.sstables[id].table_size()))
+            .chain(
+                (1..=state.levels.len())
+                    .filter(|level| !state.level_sstables(*level).is_empty())
+                    .map(|level| (SortedRun::Level(level), state.level_size(level))),
+            )
+            .collect()
+    }
+
+    // merge the sorted runs in [start, end)
+    fn task(
+        state: &LsmEngineState,
+        runs: &[(SortedRun, usize)],
+        start: usize,
+        end: usize,
+    ) -> CompactionTask {
+        let mut l0_sstables = Vec::new();
+        let mut levels = Vec::new();
+        for (run, _) in &runs[start..end] {
+            match run {
+                SortedRun::L0(id) => l0_sstables.push(*id),
+                SortedRun::Level(level) => {
+                    levels.push((*level, state.level_sstables(*level).to_vec()))
+                }
+            }
+        }
+
+        // output to the level of the oldest run merged, or the level above the next
+        // run if only level 0 sstables are merged
+        let num_levels = state.levels.len();
+        let output_level = match (runs[end - 1].0, runs.get(end)) {
+            (SortedRun::Level(level), _) => level,
+            (SortedRun::L0(_), Some((SortedRun::Level(level), _))) => level - 1,
+            (SortedRun::L0(_), Some((SortedRun::L0(_), _))) => 0,
+            (SortedRun::L0(_), None) => num_levels,
+        };
+
+        CompactionTask::Tiered(TieredCompactionTask {
+            l0_sstables,
+            levels,
+            output_level,
+            is_output_bottom: end == runs.len(),
+        })
+    }
+}
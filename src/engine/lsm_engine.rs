@@ -12,23 +12,1988 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
 
 use super::LsmEngineInner;
 use super::LsmOptions;
-use crate::memtable::Memtable;
+use crate::compact::CompactRangeOptions;
 
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
 }
 
+// how often the compaction thread checks whether there is something to compact
+const COMPACTION_INTERVAL: Duration = Duration::from_millis(50);
+
 pub struct LsmEngine {
     inner: Arc<LsmEngineInner>,
+    // dropping the sender stops the compaction thread
+    compaction_stop: Option<Sender<()>>,
+    compaction_thread: Option<JoinHandle<()>>,
 }
 
 impl LsmEngine {
-    // pub fn open(options: LsmOptions) -> Result<Self> {
-    // Ok(Self {})
-    // }
+    pub fn open(path: impl AsRef<Path>, options: LsmOptions) -> Result<Self> {
+        let inner = Arc::new(LsmEngineInner::open(path, options)?);
+        let mut engine = Self {
+            inner,
+            compaction_stop: None,
+            compaction_thread: None,
+        };
+        if engine.inner.compaction_controller().is_enabled() {
+            let (sender, receiver) = mpsc::channel();
+            engine.compaction_thread = Some(Self::spawn_compaction_thread(
+                engine.inner.clone(),
+                receiver,
+            )?);
+            engine.compaction_stop = Some(sender);
+        }
+        Ok(engine)
+    }
+
+    fn spawn_compaction_thread(
+        inner: Arc<LsmEngineInner>,
+        stop: Receiver<()>,
+    ) -> Result<JoinHandle<()>> {
+        let thread = std::thread::Builder::new()
+            .name("compaction".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(COMPACTION_INTERVAL) {
+                    // run the picked tasks until there is nothing to compact
+                    while let Err(TryRecvError::Empty) = stop.try_recv() {
+                        match inner.trigger_compaction() {
+                            Ok(true) => {}
+                            Ok(false) => break,
+                            Err(err) => {
+                                // stop compacting, the error is returned by the
+                                // next write and by `close`
+                                inner.set_background_error(err);
+                                return;
+                            }
+                        }
+                    }
+                }
+            })?;
+        Ok(thread)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner
+            .get_with_version(key, self.inner.mvcc().latest_version())
+    }
+
+    // get the values of keys, block reads of keys in the same sstable are batched
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner
+            .multi_get_with_version(keys, self.inner.mvcc().latest_version())
+    }
+
+    // an empty value is the same as deleting the key
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Put(key, value)])
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Del(key)])
+    }
+
+    // write the records atomically. Once it returns, the batch is synced in the wal
+    // unless `LsmOptions::sync_wal` is disabled.
+    pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
+        self.inner.write_batch(batch)?;
+        Ok(())
+    }
+
+    // flush all the data in memtables into level 0
+    pub fn force_flush(&self) -> Result<()> {
+        self.inner.force_flush()
+    }
+
+    // compact all the sstables into the bottom level
+    pub fn force_full_compaction(&self) -> Result<()> {
+        self.inner.force_full_compaction()
+    }
+
+    // compact all the sstables overlapping [start, end] into the bottom level,
+    // None means unbounded. It fails with fifo compaction.
+    pub fn compact_range(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: &CompactRangeOptions,
+    ) -> Result<()> {
+        self.inner.compact_range(start, end, options)
+    }
+
+    // ingest sstables written by `SstFileWriter`, the files are linked(or copied if
+    // the link fails) into the engine directory, the original files are untouched.
+    pub fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<()> {
+        self.inner.ingest_external_files(paths)
+    }
+
+    // bytes dropped from the tail of the manifest when the engine was opened: a
+    // partially written record, or with `LsmOptions::truncate_corrupted_manifest`,
+    // a corrupted record and all the records after it, whose changes are lost.
+    pub fn manifest_truncated_bytes(&self) -> usize {
+        self.inner.manifest_truncated_bytes()
+    }
+
+    // stop the compaction thread, return the error if the background compaction
+    // has failed
+    pub fn close(mut self) -> Result<()> {
+        self.stop_compaction_thread();
+        self.inner.check_background_error()
+    }
+
+    fn stop_compaction_thread(&mut self) {
+        self.compaction_stop.take();
+        if let Some(thread) = self.compaction_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for LsmEngine {
+    fn drop(&mut self) {
+        self.stop_compaction_thread();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use super::LsmEngine;
+    use crate::base::KeyBytes;
+    use crate::base::KeySlice;
+    use crate::base::Version;
+    use crate::compact::CompactRangeOptions;
+    use crate::compact::CompactionDecision;
+    use crate::compact::CompactionFilter;
+    use crate::compact::CompactionOptions;
+    use crate::compact::CompactionTask;
+    use crate::compact::FifoCompactionController;
+    use crate::compact::FifoCompactionOptions;
+    use crate::compact::LeveledCompactionController;
+    use crate::compact::LeveledCompactionOptions;
+    use crate::compact::TieredCompactionOptions;
+    use crate::engine::LsmEngineState;
+    use crate::engine::LsmOptions;
+    use crate::engine::Manifest;
+    use crate::engine::ManifestRecord;
+    use crate::engine::WriteBatchRecord;
+    use crate::fs::FaultInjectionFileSystem;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+    use crate::fs::IoPriority;
+    use crate::fs::MemFileSystem;
+    use crate::fs::PosixFileSystem;
+    use crate::fs::RandomAccessFile;
+    use crate::fs::RateLimiter;
+    use crate::fs::WritableFile;
+    use crate::fs::run_crash_test;
+    use crate::table::SsTableBuilder;
+    use crate::table::SstFileWriter;
+    use crate::table::TablePropertiesCollector;
+    use crate::table::TablePropertiesCollectorFactory;
+
+    fn write_external_file(
+        path: &std::path::Path,
+        keys: impl Iterator<Item = usize>,
+        value_prefix: &str,
+    ) -> Result<()> {
+        let mut writer = SstFileWriter::create(path, &LsmOptions::default())?;
+        for i in keys {
+            let value = format!("{}_{}", value_prefix, i);
+            writer.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn get(engine: &LsmEngine, i: usize) -> Result<Option<Bytes>> {
+        engine.get(format!("key_{:05}", i).as_bytes())
+    }
+
+    #[test]
+    fn test_put_get_and_flush() -> Result<()> {
+        let dir = tempdir()?;
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+        engine.put(b"hello", b"world")?;
+        engine.put(b"test", b"case")?;
+        assert_eq!(engine.get(b"hello")?, Some(Bytes::from("world")));
+
+        engine.force_flush()?;
+        assert_eq!(engine.inner.state.read().l0_sstables.len(), 1);
+        assert_eq!(engine.get(b"hello")?, Some(Bytes::from("world")));
+
+        // newer versions in memtable shadow the flushed ones
+        engine.put(b"hello", b"world2")?;
+        engine.delete(b"test")?;
+        assert_eq!(engine.get(b"hello")?, Some(Bytes::from("world2")));
+        assert!(engine.get(b"test")?.is_none());
+
+        engine.force_flush()?;
+        assert_eq!(engine.inner.state.read().l0_sstables.len(), 2);
+        assert_eq!(engine.get(b"hello")?, Some(Bytes::from("world2")));
+        assert!(engine.get(b"test")?.is_none());
+        assert!(engine.get(b"none")?.is_none());
+
+        // read at an old version
+        assert_eq!(
+            engine.inner.get_with_version(b"test", 2)?,
+            Some(Bytes::from("case"))
+        );
+        assert_eq!(
+            engine.inner.get_with_version(b"hello", 2)?,
+            Some(Bytes::from("world"))
+        );
+
+        // flushing an empty memtable does nothing
+        engine.force_flush()?;
+        assert_eq!(engine.inner.state.read().l0_sstables.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_when_memtable_is_full() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            memtable_size: 1024,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        for i in 0..1000 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+        }
+        let state = engine.inner.state.read().clone();
+        assert!(state.l0_sstables.len() > 1);
+        assert!(state.imm_memtables.is_empty());
+        for i in 0..1000 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_hot_key() -> Result<()> {
+        let dir = tempdir()?;
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+        for i in 0..100 {
+            engine.put(b"hot_key", format!("value_{}", i).as_bytes())?;
+        }
+        engine.force_flush()?;
+        assert_eq!(engine.inner.state.read().l0_sstables.len(), 1);
+        assert_eq!(engine.get(b"hot_key")?, Some(Bytes::from("value_99")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_get() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let options = LsmOptions {
+            block_size: 128,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+
+        // keys spread over a bottom level table, level 0 tables and memtable
+        let file = external_dir.path().join("a.sst");
+        write_external_file(&file, 0..1000, "a")?;
+        engine.ingest_external_files(&[&file])?;
+        for i in (0..1000).step_by(3) {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"l0")?;
+        }
+        engine.force_flush()?;
+        for i in (0..1000).step_by(5) {
+            engine.delete(format!("key_{:05}", i).as_bytes())?;
+        }
+
+        let keys: Vec<String> = (0..1100).map(|i| format!("key_{:05}", i)).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_bytes()).collect();
+        let values = engine.multi_get(&keys)?;
+        assert_eq!(values.len(), keys.len());
+        for (i, value) in values.into_iter().enumerate() {
+            assert_eq!(value, get(&engine, i)?);
+            let expected = if i >= 1000 || i % 5 == 0 {
+                None
+            } else if i % 3 == 0 {
+                Some(Bytes::from("l0"))
+            } else {
+                Some(Bytes::from(format!("a_{}", i)))
+            };
+            assert_eq!(value, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_open_files() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            max_open_files: 2,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        for i in 0..10 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            engine.force_flush()?;
+        }
+        assert_eq!(engine.inner.state.read().l0_sstables.len(), 10);
+        assert!(engine.inner.table_cache.len() <= 2);
+
+        // evicted tables are opened again when they are read
+        for _ in 0..2 {
+            for i in 0..10 {
+                assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+            }
+            assert!(engine.inner.table_cache.len() <= 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_file_system() -> Result<()> {
+        let fs = Arc::new(MemFileSystem::new());
+        let options = || LsmOptions {
+            fs: fs.clone(),
+            memtable_size: 1024,
+            ..LsmOptions::default()
+        };
+        let path = Path::new("/db");
+        let engine = LsmEngine::open(path, options())?;
+        for i in 0..1000 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+        }
+        engine.force_flush()?;
+
+        fs.create_dir_all(Path::new("/external"))?;
+        let file = Path::new("/external/a.sst");
+        let mut writer = SstFileWriter::create(file, &options())?;
+        writer.put(b"key_10000", b"ingested")?;
+        writer.finish()?;
+        engine.ingest_external_files(&[file])?;
+
+        for i in 0..1000 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+        assert_eq!(get(&engine, 10000)?, Some(Bytes::from("ingested")));
+
+        let state = engine.inner.state.read().clone();
+        assert!(!state.l0_sstables.is_empty());
+        for id in state.sstables.keys() {
+            assert!(fs.exists(&path.join(format!("{:05}.sst", id))));
+        }
+        assert!(fs.exists(&path.join("MANIFEST")));
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_reads() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            memtable_size: 1024,
+            use_mmap_reads: true,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        for i in 0..1000 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+        }
+        engine.force_flush()?;
+        let state = engine.inner.state.read().clone();
+        assert!(!state.l0_sstables.is_empty());
+        for i in 0..1000 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+        assert!(get(&engine, 1000)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_direct_io() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            use_mmap_reads: true,
+            use_direct_reads: true,
+            ..LsmOptions::default()
+        };
+        assert!(LsmEngine::open(dir.path(), options).is_err());
+
+        let options = LsmOptions {
+            memtable_size: 1024,
+            use_direct_reads: true,
+            use_direct_io_for_flush_and_compaction: true,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        for i in 0..1000 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+        }
+        engine.force_flush()?;
+        let state = engine.inner.state.read().clone();
+        assert!(!state.l0_sstables.is_empty());
+        for i in 0..1000 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+        Ok(())
+    }
+
+    // in-memory file system remembering the options of the opened files
+    #[derive(Default)]
+    struct OpenRecordingFileSystem {
+        base: MemFileSystem,
+        opened: parking_lot::Mutex<Vec<(std::path::PathBuf, FileOptions)>>,
+    }
+
+    impl FileSystem for OpenRecordingFileSystem {
+        fn create(&self, path: &Path, options: FileOptions) -> Result<Box<dyn WritableFile>> {
+            self.base.create(path, options)
+        }
+
+        fn append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+            self.base.append(path)
+        }
+
+        fn open(&self, path: &Path, options: FileOptions) -> Result<Box<dyn RandomAccessFile>> {
+            self.opened.lock().push((path.to_path_buf(), options));
+            self.base.open(path, options)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+            self.base.rename(from, to)
+        }
+
+        fn remove(&self, path: &Path) -> Result<()> {
+            self.base.remove(path)
+        }
+
+        fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+            self.base.hard_link(from, to)
+        }
+
+        fn list(&self, dir: &Path) -> Result<Vec<std::path::PathBuf>> {
+            self.base.list(dir)
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.base.exists(path)
+        }
+
+        fn create_dir_all(&self, path: &Path) -> Result<()> {
+            self.base.create_dir_all(path)
+        }
+
+        fn sync_dir(&self, dir: &Path) -> Result<()> {
+            self.base.sync_dir(dir)
+        }
+    }
+
+    #[test]
+    fn test_direct_io_compaction_reads() -> Result<()> {
+        let fs = Arc::new(OpenRecordingFileSystem::default());
+        let options = LsmOptions {
+            fs: fs.clone(),
+            use_mmap_reads: true,
+            use_direct_io_for_flush_and_compaction: true,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open("/db", options)?;
+        for round in 0..2 {
+            for i in 0..100 {
+                let value = format!("value_{}", round);
+                engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+            }
+            engine.force_flush()?;
+        }
+        let inputs = engine.inner.state.read().l0_sstables.clone();
+        fs.opened.lock().clear();
+        engine.force_full_compaction()?;
+
+        // the inputs are read with direct I/O instead of the mmap of user reads
+        let opened = std::mem::take(&mut *fs.opened.lock());
+        for id in inputs {
+            let path = engine.inner.path_of_sst(id as usize);
+            let (_, options) = opened.iter().find(|(opened, _)| *opened == path).unwrap();
+            assert!(options.use_direct_reads && !options.use_mmap);
+        }
+        for i in 0..100 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value_1")));
+        }
+        assert!(fs.opened.lock().iter().all(|(_, options)| options.use_mmap));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ingest_external_files() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+
+        // non-overlapping files go to the bottom level
+        let file_a = external_dir.path().join("a.sst");
+        let file_b = external_dir.path().join("b.sst");
+        write_external_file(&file_a, 0..100, "a")?;
+        write_external_file(&file_b, 200..300, "b")?;
+        engine.ingest_external_files(&[&file_b, &file_a])?;
+        {
+            let state = engine.inner.state.read().clone();
+            assert!(state.l0_sstables.is_empty());
+            assert_eq!(state.levels.last().unwrap().1.len(), 2);
+        }
+        assert_eq!(get(&engine, 50)?, Some(Bytes::from("a_50")));
+        assert_eq!(get(&engine, 250)?, Some(Bytes::from("b_250")));
+        assert!(get(&engine, 150)?.is_none());
+        // the original files are untouched
+        assert!(file_a.exists() && file_b.exists());
+
+        // the ingested file overlaps memtable, so memtable is flushed first
+        // and the ingested keys shadow the old ones
+        engine.put(b"key_00050", b"memtable")?;
+        engine.put(b"key_00150", b"memtable")?;
+        let file_c = external_dir.path().join("c.sst");
+        write_external_file(&file_c, 40..160, "c")?;
+        engine.ingest_external_files(&[&file_c])?;
+        {
+            let state = engine.inner.state.read().clone();
+            assert!(state.memtable.is_empty());
+            assert_eq!(state.l0_sstables.len(), 2);
+        }
+        assert_eq!(get(&engine, 30)?, Some(Bytes::from("a_30")));
+        assert_eq!(get(&engine, 50)?, Some(Bytes::from("c_50")));
+        assert_eq!(get(&engine, 150)?, Some(Bytes::from("c_150")));
+
+        // writes after ingestion are newer
+        engine.put(b"key_00050", b"new")?;
+        assert_eq!(get(&engine, 50)?, Some(Bytes::from("new")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ingest_invalid_files() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+
+        // overlapping files in one batch
+        let file_a = external_dir.path().join("a.sst");
+        let file_b = external_dir.path().join("b.sst");
+        write_external_file(&file_a, 0..100, "a")?;
+        write_external_file(&file_b, 99..200, "b")?;
+        assert!(engine.ingest_external_files(&[&file_a, &file_b]).is_err());
+
+        // files with versioned keys
+        let file_c = external_dir.path().join("c.sst");
+        let mut builder = SsTableBuilder::create(4096)?;
+        builder.add(
+            KeyBytes::new(Bytes::from("key"), 10).to_key_slice(),
+            b"value",
+        )?;
+        builder.build(0, None, &file_c)?;
+        assert!(engine.ingest_external_files(&[&file_c]).is_err());
+
+        let state = engine.inner.state.read().clone();
+        assert!(state.sstables.is_empty());
+        assert!(get(&engine, 0)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_compaction() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let options = LsmOptions {
+            block_size: 128,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+
+        // an ingested table in the bottom level, overwritten and deleted keys in level 0
+        let file = external_dir.path().join("a.sst");
+        write_external_file(&file, 0..300, "a")?;
+        engine.ingest_external_files(&[&file])?;
+        let mut round0_version = 0;
+        for round in 0..3 {
+            for i in (round * 100..310).step_by(2) {
+                let value = format!("value_{}_{}", round, i);
+                engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+            }
+            engine.delete(format!("key_{:05}", round * 7 + 1).as_bytes())?;
+            engine.force_flush()?;
+            if round == 0 {
+                // a snapshot keeps the versions it reads from garbage collection
+                round0_version = engine.inner.mvcc().latest_version();
+                engine
+                    .inner
+                    .mvcc()
+                    .version
+                    .lock()
+                    .1
+                    .add_reader(round0_version);
+            }
+        }
+        let expected = (0..320)
+            .map(|i| get(&engine, i))
+            .collect::<Result<Vec<_>>>()?;
+        let old_value = engine
+            .inner
+            .get_with_version(b"key_00200", round0_version)?;
+        assert_eq!(old_value, Some(Bytes::from("value_0_200")));
+        let inputs: Vec<_> = engine.inner.state.read().sstables.keys().copied().collect();
+        assert_eq!(inputs.len(), 4);
+
+        engine.force_full_compaction()?;
+        {
+            let state = engine.inner.state.read();
+            assert!(state.l0_sstables.is_empty());
+            assert_eq!(state.sstables.len(), 1);
+            assert_eq!(state.levels.last().unwrap().1.len(), 1);
+        }
+        for (i, value) in expected.into_iter().enumerate() {
+            assert_eq!(get(&engine, i)?, value);
+        }
+        // the versions read by the snapshot are kept
+        assert_eq!(
+            engine
+                .inner
+                .get_with_version(b"key_00200", round0_version)?,
+            old_value
+        );
+        for id in inputs {
+            assert!(!engine.inner.path_of_sst(id as usize).exists());
+        }
+        engine
+            .inner
+            .mvcc()
+            .version
+            .lock()
+            .1
+            .remove_reader(round0_version);
+
+        // files of compacted sstables are kept until no reader holds them
+        engine.put(b"key_00000", b"new")?;
+        engine.force_flush()?;
+        let snapshot = engine.inner.state.read().clone();
+        engine.force_full_compaction()?;
+        for id in snapshot.sstables.keys() {
+            assert!(engine.inner.path_of_sst(*id as usize).exists());
+        }
+        drop(snapshot);
+        engine.force_full_compaction()?;
+        assert_eq!(engine.inner.state.read().sstables.len(), 1);
+        let num_sst_files = dir
+            .path()
+            .read_dir()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension() == Some("sst".as_ref()))
+            .count();
+        assert_eq!(num_sst_files, 1);
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("new")));
+
+        Ok(())
+    }
+
+    // write `rounds` versions of 1000 keys with some deletes, then wait for
+    // compaction to finish and check the data and the shape of levels
+    fn run_compaction_workload(
+        compaction_options: CompactionOptions,
+        rounds: usize,
+    ) -> Result<Arc<LsmEngineState>> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            memtable_size: 4096,
+            target_file_size: 4096,
+            num_levels: 4,
+            compaction_options,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        for round in 0..rounds {
+            for i in 0..1000 {
+                let value = format!("value_{}_{}", round, i);
+                engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+            }
+        }
+        for i in (0..1000).step_by(7) {
+            engine.delete(format!("key_{:05}", i).as_bytes())?;
+        }
+        engine.force_flush()?;
+        while engine.inner.trigger_compaction()? {}
+
+        // sstables of a level are sorted and never overlap
+        let state = engine.inner.state.read().clone();
+        for (_, ids) in &state.levels {
+            for pair in ids.windows(2) {
+                assert!(
+                    state.sstables[&pair[0]].last_key().key_ref()
+                        < state.sstables[&pair[1]].first_key().key_ref()
+                );
+            }
+        }
+        // outputs are cut at the target file size
+        assert!(state.levels.iter().any(|(_, ids)| ids.len() > 1));
+
+        for i in 0..1000 {
+            let expected = if i % 7 == 0 {
+                None
+            } else {
+                Some(Bytes::from(format!("value_{}_{}", rounds - 1, i)))
+            };
+            assert_eq!(get(&engine, i)?, expected);
+        }
+        Ok(state)
+    }
+
+    fn run_leveled_compaction(
+        leveled_options: LeveledCompactionOptions,
+        rounds: usize,
+    ) -> Result<Arc<LsmEngineState>> {
+        let state =
+            run_compaction_workload(CompactionOptions::Leveled(leveled_options.clone()), rounds)?;
+        assert!(state.l0_sstables.len() < leveled_options.level0_file_num_compaction_trigger);
+        let (_, targets) =
+            LeveledCompactionController::new(leveled_options).target_level_sizes(&state);
+        for level in 1..state.levels.len() {
+            assert!(state.level_size(level) <= targets[level - 1]);
+        }
+        Ok(state)
+    }
+
+    #[test]
+    fn test_leveled_compaction() -> Result<()> {
+        let state = run_leveled_compaction(
+            LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                max_bytes_for_level_base: 4 << 10,
+                max_bytes_for_level_multiplier: 2,
+                level_compaction_dynamic_level_bytes: false,
+                ..LeveledCompactionOptions::default()
+            },
+            4,
+        )?;
+        // data is pushed down to the last level
+        assert!(!state.levels.last().unwrap().1.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_leveled_compaction_dynamic_level_bytes() -> Result<()> {
+        let state = run_leveled_compaction(
+            LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                max_bytes_for_level_base: 16 << 10,
+                max_bytes_for_level_multiplier: 2,
+                level_compaction_dynamic_level_bytes: true,
+                ..LeveledCompactionOptions::default()
+            },
+            2,
+        )?;
+        // level 0 is compacted into the last level first, level 1 is still empty
+        assert!(state.levels[0].1.is_empty());
+        let last_level_size = state.level_size(state.levels.len());
+        for level in 1..state.levels.len() {
+            assert!(state.level_size(level) < last_level_size);
+        }
+        Ok(())
+    }
+
+    // fails the writes of the file system once more than `num_tables` sstables are built
+    struct FailWritesAfterTables {
+        fs: Arc<FaultInjectionFileSystem>,
+        num_tables: usize,
+        built: AtomicUsize,
+    }
+
+    impl TablePropertiesCollectorFactory for FailWritesAfterTables {
+        fn name(&self) -> &str {
+            "fail_writes_after_tables"
+        }
+
+        fn create(&self) -> Box<dyn TablePropertiesCollector> {
+            if self.built.fetch_add(1, Ordering::SeqCst) >= self.num_tables {
+                self.fs.fail_writes_after(Some(0));
+            }
+            Box::new(EmptyCollector)
+        }
+    }
+
+    struct EmptyCollector;
+
+    impl TablePropertiesCollector for EmptyCollector {
+        fn add(&mut self, _key: KeySlice, _value: &[u8]) {}
+
+        fn finish(&mut self) -> BTreeMap<String, Vec<u8>> {
+            BTreeMap::new()
+        }
+    }
+
+    #[test]
+    fn test_background_compaction_error() -> Result<()> {
+        let fs = Arc::new(FaultInjectionFileSystem::new(
+            Arc::new(MemFileSystem::new()),
+        ));
+        // the two flushed sstables are written, the compaction output is not
+        let factory = Arc::new(FailWritesAfterTables {
+            fs: fs.clone(),
+            num_tables: 2,
+            built: AtomicUsize::new(0),
+        });
+        let options = LsmOptions {
+            fs: fs.clone(),
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                ..LeveledCompactionOptions::default()
+            }),
+            table_properties_collector_factories: vec![factory],
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open("/db", options)?;
+        for _ in 0..2 {
+            engine.put(b"key_00000", b"value")?;
+            engine.put(b"key_00001", b"value")?;
+            engine.force_flush()?;
+        }
+        // the compaction thread fails in writing the output and stops
+        let start = Instant::now();
+        while engine.inner.check_background_error().is_ok() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        fs.fail_writes_after(None);
+
+        // writes and close return the error, reads still work
+        let err = engine.put(b"key_00002", b"value").unwrap_err();
+        assert!(err.to_string().contains("background compaction failed"));
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("value")));
+        assert!(engine.close().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiered_compaction() -> Result<()> {
+        let tiered_options = TieredCompactionOptions {
+            level0_file_num_compaction_trigger: 4,
+            ..TieredCompactionOptions::default()
+        };
+        let state = run_compaction_workload(CompactionOptions::Tiered(tiered_options), 4)?;
+
+        // fewer sorted runs than the trigger are left
+        let num_sorted_runs = state.l0_sstables.len()
+            + state
+                .levels
+                .iter()
+                .filter(|(_, ids)| !ids.is_empty())
+                .count();
+        assert!(num_sorted_runs < 4);
+        assert!(!state.levels.last().unwrap().1.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fifo_compaction() -> Result<()> {
+        let dir = tempdir()?;
+        // every flush writes 100 keys of another range into level 0
+        let write_round = |engine: &LsmEngine, round: usize| -> Result<()> {
+            for i in round * 100..(round + 1) * 100 {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            engine.force_flush()
+        };
+        let table_size = {
+            let engine = LsmEngine::open(dir.path().join("probe"), LsmOptions::default())?;
+            write_round(&engine, 0)?;
+            let state = engine.inner.state.read().clone();
+            state.sstables[&state.l0_sstables[0]].table_size()
+        };
+
+        let fifo_options = FifoCompactionOptions {
+            max_table_files_size: table_size * 3,
+            ttl: 0,
+        };
+        let options = LsmOptions {
+            compaction_options: CompactionOptions::Fifo(fifo_options),
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path().join("db"), options)?;
+        for round in 0..5 {
+            write_round(&engine, round)?;
+        }
+        let dropped: Vec<_> = engine.inner.state.read().l0_sstables[3..].to_vec();
+        while engine.inner.trigger_compaction()? {}
+
+        // the oldest two tables are dropped without merging
+        let state = engine.inner.state.read().clone();
+        assert_eq!(state.l0_sstables.len(), 3);
+        assert!(
+            state
+                .sstables
+                .values()
+                .all(|info| !dropped.contains(&info.id()))
+        );
+        for id in dropped {
+            assert!(!engine.inner.path_of_sst(id as usize).exists());
+        }
+        assert!(get(&engine, 199)?.is_none());
+        assert_eq!(get(&engine, 200)?, Some(Bytes::from("value")));
+        assert_eq!(get(&engine, 499)?, Some(Bytes::from("value")));
+
+        // all the tables expire after ttl
+        let controller = FifoCompactionController::new(FifoCompactionOptions {
+            max_table_files_size: usize::MAX,
+            ttl: 3600,
+        });
+        let creation_time = state
+            .sstables
+            .values()
+            .map(|info| info.properties().creation_time)
+            .max()
+            .unwrap();
+        assert!(
+            controller
+                .generate_compaction_task_at(&state, creation_time + 10)
+                .is_none()
+        );
+        let Some(CompactionTask::Fifo(task)) =
+            controller.generate_compaction_task_at(&state, creation_time + 3600)
+        else {
+            panic!("expired tables are not dropped");
+        };
+        let mut expected = state.l0_sstables.clone();
+        expected.reverse();
+        assert_eq!(task.sstables, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fifo_compaction_with_ingestion() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+        for round in 0..2 {
+            for i in round * 100..(round + 1) * 100 {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            engine.force_flush()?;
+        }
+        // the ingested sstable is put into the last level, but has the newest data
+        let external_file = external_dir.path().join("a.sst");
+        write_external_file(&external_file, 500..600, "a")?;
+        engine.ingest_external_files(&[&external_file])?;
+        let state = engine.inner.state.read().clone();
+        let ingested = state.levels.last().unwrap().1[0];
+        let total_size: usize = state.sstables.values().map(|info| info.table_size()).sum();
+
+        let controller = FifoCompactionController::new(FifoCompactionOptions {
+            max_table_files_size: total_size - 1,
+            ttl: 0,
+        });
+        let Some(CompactionTask::Fifo(task)) = controller.generate_compaction_task(&state) else {
+            panic!("no table is dropped");
+        };
+        assert_eq!(task.sstables, vec![state.l0_sstables[1]]);
+
+        // all of them expire, from the oldest data
+        let controller = FifoCompactionController::new(FifoCompactionOptions {
+            max_table_files_size: usize::MAX,
+            ttl: 1,
+        });
+        let creation_time = state
+            .sstables
+            .values()
+            .map(|info| info.properties().creation_time)
+            .max()
+            .unwrap();
+        let Some(CompactionTask::Fifo(task)) =
+            controller.generate_compaction_task_at(&state, creation_time + 1)
+        else {
+            panic!("expired tables are not dropped");
+        };
+        assert_eq!(task.sstables, vec![
+            state.l0_sstables[1],
+            state.l0_sstables[0],
+            ingested
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_range() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            target_file_size: 4096,
+            num_levels: 3,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        for round in 0..4 {
+            for i in 0..400 {
+                engine.put(
+                    format!("key_{:05}", i).as_bytes(),
+                    format!("value_{}_{}", i, round).as_bytes(),
+                )?;
+            }
+            engine.force_flush()?;
+        }
+        // left in memtable, flushed by compact_range
+        engine.put(b"key_00150", b"value_150_memtable")?;
+
+        let progress = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let progress_clone = progress.clone();
+        let options = CompactRangeOptions {
+            exclusive_manual_compaction: true,
+            progress: Some(Box::new(move |progress| {
+                progress_clone
+                    .lock()
+                    .push((progress.level, progress.input_sstables));
+            })),
+        };
+        engine.compact_range(Some(b"key_00100"), Some(b"key_00199"), &options)?;
+
+        // every level is reported, all the level 0 sstables overlap the range
+        let progress = progress.lock().clone();
+        assert_eq!(
+            progress.iter().map(|(level, _)| *level).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(progress[0].1, 5);
+        {
+            let state = engine.inner.state.read();
+            assert!(state.l0_sstables.is_empty());
+            for level in 1..3 {
+                assert!(!state.level_overlaps(level, b"key_00100", b"key_00199"));
+            }
+            assert!(state.level_overlaps(3, b"key_00100", b"key_00199"));
+        }
+        for i in 0..400 {
+            let expected = if i == 150 {
+                "value_150_memtable".to_string()
+            } else {
+                format!("value_{}_3", i)
+            };
+            assert_eq!(get(&engine, i)?, Some(Bytes::from(expected)));
+        }
+
+        engine.compact_range(None, None, &CompactRangeOptions {
+            exclusive_manual_compaction: false,
+            progress: None,
+        })?;
+        let state = engine.inner.state.read().clone();
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.level_sstables(1).is_empty());
+        assert!(state.level_sstables(2).is_empty());
+        assert!(!state.level_sstables(3).is_empty());
+        assert_eq!(get(&engine, 399)?, Some(Bytes::from("value_399_3")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_range_tiered() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            compaction_options: CompactionOptions::Tiered(TieredCompactionOptions {
+                level0_file_num_compaction_trigger: 100,
+                ..TieredCompactionOptions::default()
+            }),
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        // three sorted runs in level 0, the newest one is out of the range
+        for (round, keys) in [0..100, 200..300, 0..100].into_iter().enumerate() {
+            for i in keys {
+                let value = format!("value_{}_{}", i, round);
+                engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+            }
+            engine.force_flush()?;
+        }
+        let newest = engine.inner.state.read().l0_sstables[0];
+
+        let progress = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let progress_clone = progress.clone();
+        let options = CompactRangeOptions {
+            exclusive_manual_compaction: true,
+            progress: Some(Box::new(move |progress| {
+                progress_clone
+                    .lock()
+                    .push((progress.level, progress.input_sstables));
+            })),
+        };
+        engine.compact_range(Some(b"key_00250"), Some(b"key_00260"), &options)?;
+
+        // the two older runs are merged into the bottom level
+        assert_eq!(progress.lock().clone(), vec![(0, 2)]);
+        {
+            let state = engine.inner.state.read();
+            assert_eq!(state.l0_sstables, vec![newest]);
+            assert!(!state.levels.last().unwrap().1.is_empty());
+        }
+
+        engine.compact_range(None, None, &CompactRangeOptions::default())?;
+        let state = engine.inner.state.read().clone();
+        assert!(state.l0_sstables.is_empty());
+        assert_eq!(
+            state
+                .levels
+                .iter()
+                .filter(|(_, ids)| !ids.is_empty())
+                .count(),
+            1
+        );
+        for i in (0..100).chain(200..300) {
+            let round = if i < 100 { 2 } else { 1 };
+            let expected = format!("value_{}_{}", i, round);
+            assert_eq!(get(&engine, i)?, Some(Bytes::from(expected)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_range_fifo() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            compaction_options: CompactionOptions::Fifo(FifoCompactionOptions::default()),
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        engine.put(b"key_00000", b"value")?;
+        engine.force_flush()?;
+
+        // fifo compaction only drops sstables, the range is not compacted
+        assert!(
+            engine
+                .compact_range(None, None, &CompactRangeOptions::default())
+                .is_err()
+        );
+        assert_eq!(engine.inner.state.read().l0_sstables.len(), 1);
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("value")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subcompactions() -> Result<()> {
+        // compact the same data with and without subcompactions
+        let run = |max_subcompactions: usize| -> Result<(Vec<Option<Bytes>>, LsmEngineState)> {
+            let dir = tempdir()?;
+            let options = LsmOptions {
+                block_size: 128,
+                target_file_size: 4096,
+                max_subcompactions,
+                ..LsmOptions::default()
+            };
+            let engine = LsmEngine::open(dir.path(), options)?;
+            let mut old_version = 0;
+            for round in 0..4 {
+                for i in (round * 50..1000).step_by(round + 1) {
+                    let value = format!("value_{}_{}", round, i);
+                    engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+                }
+                engine.delete(format!("key_{:05}", round * 11).as_bytes())?;
+                engine.force_flush()?;
+                if round == 0 {
+                    old_version = engine.inner.mvcc().latest_version();
+                    engine.inner.mvcc().version.lock().1.add_reader(old_version);
+                }
+            }
+            engine.force_full_compaction()?;
+
+            let mut values = (0..1010)
+                .map(|i| get(&engine, i))
+                .collect::<Result<Vec<_>>>()?;
+            values.push(engine.inner.get_with_version(b"key_00600", old_version)?);
+            let state = engine.inner.state.read().as_ref().clone();
+            Ok((values, state))
+        };
+
+        let (expected, _) = run(1)?;
+        let (values, state) = run(4)?;
+        assert_eq!(values, expected);
+        assert_eq!(values[1010], Some(Bytes::from("value_0_600")));
+
+        // outputs of the key ranges make one sorted level
+        assert!(state.l0_sstables.is_empty());
+        let (_, bottom) = state.levels.last().unwrap();
+        assert!(bottom.len() >= 4);
+        for pair in bottom.windows(2) {
+            assert!(
+                state.sstables[&pair[0]].last_key().key_ref()
+                    < state.sstables[&pair[1]].first_key().key_ref()
+            );
+        }
+
+        Ok(())
+    }
+
+    // removes "expired" values and changes "old" values to "new"
+    struct TestCompactionFilter {
+        max_version: AtomicU64,
+    }
+
+    impl CompactionFilter for TestCompactionFilter {
+        fn filter(
+            &self,
+            _level: usize,
+            _key: &[u8],
+            version: Version,
+            value: &[u8],
+        ) -> CompactionDecision {
+            self.max_version.fetch_max(version, Ordering::SeqCst);
+            match value {
+                b"expired" => CompactionDecision::Remove,
+                b"old" => CompactionDecision::ChangeValue(b"new".to_vec()),
+                _ => CompactionDecision::Keep,
+            }
+        }
+    }
+
+    #[test]
+    fn test_compaction_filter() -> Result<()> {
+        let dir = tempdir()?;
+        let filter = Arc::new(TestCompactionFilter {
+            max_version: AtomicU64::new(0),
+        });
+        let options = LsmOptions {
+            compaction_filter: Some(filter.clone()),
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        engine.put(b"a", b"expired")?;
+        engine.put(b"b", b"old")?;
+        engine.put(b"c", b"value")?;
+        engine.force_flush()?;
+
+        // a snapshot holds the watermark, newer versions are not filtered
+        let snapshot = engine.inner.mvcc().latest_version();
+        engine.inner.mvcc().version.lock().1.add_reader(snapshot);
+        engine.put(b"b", b"old")?;
+        engine.put(b"d", b"expired")?;
+        engine.force_flush()?;
+        engine.force_full_compaction()?;
+
+        assert_eq!(filter.max_version.load(Ordering::SeqCst), snapshot);
+        assert_eq!(engine.get(b"a")?, None);
+        assert_eq!(engine.get(b"c")?, Some(Bytes::from("value")));
+        assert_eq!(engine.get(b"b")?, Some(Bytes::from("old")));
+        assert_eq!(engine.get(b"d")?, Some(Bytes::from("expired")));
+        assert_eq!(
+            engine.inner.get_with_version(b"b", snapshot)?,
+            Some(Bytes::from("new"))
+        );
+
+        engine.inner.mvcc().version.lock().1.remove_reader(snapshot);
+        engine.force_full_compaction()?;
+        assert_eq!(engine.get(b"b")?, Some(Bytes::from("new")));
+        assert_eq!(engine.get(b"d")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_gc() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                ..LeveledCompactionOptions::default()
+            }),
+            num_levels: 2,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        let num_entries = |engine: &LsmEngine| -> (u64, u64) {
+            let state = engine.inner.state.read();
+            state
+                .sstables
+                .values()
+                .fold((0, 0), |(entries, tombstones), info| {
+                    let properties = info.properties();
+                    (
+                        entries + properties.num_entries,
+                        tombstones + properties.num_tombstones,
+                    )
+                })
+        };
+
+        // the tombstone is kept above the bottom level, the older version is dropped
+        engine.put(b"a", b"1")?;
+        engine.force_flush()?;
+        engine.delete(b"a")?;
+        engine.force_flush()?;
+        while engine.inner.trigger_compaction()? {}
+        assert_eq!(engine.inner.state.read().level_sstables(1).len(), 1);
+        assert_eq!(num_entries(&engine), (1, 1));
+        assert_eq!(engine.get(b"a")?, None);
+
+        // versions read by the snapshot are kept
+        engine.put(b"b", b"1")?;
+        engine.put(b"c", b"1")?;
+        let snapshot = engine.inner.mvcc().latest_version();
+        engine.inner.mvcc().version.lock().1.add_reader(snapshot);
+        engine.put(b"b", b"2")?;
+        engine.put(b"b", b"3")?;
+        engine.delete(b"c")?;
+        engine.force_flush()?;
+        engine.force_full_compaction()?;
+        // b@1, b@2, b@3, c@1 and the tombstone of c, the tombstone of a is dropped
+        assert_eq!(num_entries(&engine), (5, 1));
+        assert_eq!(
+            engine.inner.get_with_version(b"b", snapshot)?,
+            Some(Bytes::from("1"))
+        );
+        assert_eq!(
+            engine.inner.get_with_version(b"c", snapshot)?,
+            Some(Bytes::from("1"))
+        );
+
+        // only the latest version is kept without snapshots, tombstones
+        // in the bottom level are dropped
+        engine.inner.mvcc().version.lock().1.remove_reader(snapshot);
+        engine.force_full_compaction()?;
+        assert_eq!(num_entries(&engine), (1, 0));
+        assert_eq!(engine.get(b"b")?, Some(Bytes::from("3")));
+        assert_eq!(engine.get(b"c")?, None);
+
+        engine.delete(b"b")?;
+        engine.force_flush()?;
+        engine.force_full_compaction()?;
+        assert!(engine.inner.state.read().sstables.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limiter() -> Result<()> {
+        let dir = tempdir()?;
+        let rate_limiter = Arc::new(RateLimiter::new(64 << 20));
+        let options = LsmOptions {
+            rate_limiter: Some(rate_limiter.clone()),
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+
+        // flush writes with high priority, compaction with low priority
+        for round in 0..2 {
+            for i in 0..100 {
+                engine.put(
+                    format!("key_{:05}", i).as_bytes(),
+                    format!("value_{}", round).as_bytes(),
+                )?;
+            }
+            engine.force_flush()?;
+        }
+        let flushed: usize = {
+            let state = engine.inner.state.read();
+            state.sstables.values().map(|info| info.table_size()).sum()
+        };
+        assert_eq!(
+            rate_limiter.total_bytes_through(IoPriority::High),
+            flushed as u64
+        );
+        assert_eq!(rate_limiter.total_bytes_through(IoPriority::Low), 0);
+
+        engine.force_full_compaction()?;
+        let compacted: usize = {
+            let state = engine.inner.state.read();
+            state.sstables.values().map(|info| info.table_size()).sum()
+        };
+        assert_eq!(
+            rate_limiter.total_bytes_through(IoPriority::Low),
+            compacted as u64
+        );
+
+        assert_eq!(engine.get(b"key_00000")?, Some(Bytes::from("value_1")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trivial_move() -> Result<()> {
+        let dir = tempdir()?;
+        let options = LsmOptions {
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                ..LeveledCompactionOptions::default()
+            }),
+            num_levels: 3,
+            ..LsmOptions::default()
+        };
+        let engine = LsmEngine::open(dir.path(), options)?;
+        let write_keys = |range: std::ops::Range<usize>| -> Result<()> {
+            for i in range {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            engine.force_flush()
+        };
+
+        // level 0 sstables overlapping nothing are moved into level 1
+        write_keys(0..100)?;
+        write_keys(100..200)?;
+        let mut moved = engine.inner.state.read().l0_sstables.clone();
+        while engine.inner.trigger_compaction()? {}
+        {
+            let state = engine.inner.state.read();
+            assert!(state.l0_sstables.is_empty());
+            let mut level1 = state.level_sstables(1).to_vec();
+            level1.sort();
+            moved.sort();
+            assert_eq!(level1, moved);
+        }
+        for id in &moved {
+            assert!(engine.inner.path_of_sst(*id as usize).exists());
+        }
+        let (_, recovery) =
+            Manifest::recover(&PosixFileSystem, dir.path().join("MANIFEST"), false)?;
+        let Some(ManifestRecord::Compaction(task, outputs)) = recovery.records.last() else {
+            panic!("the move is not recorded");
+        };
+        let mut outputs: Vec<_> = outputs.iter().map(|id| *id as u64).collect();
+        outputs.sort();
+        assert_eq!(outputs, moved);
+        assert_eq!(task.output_level(), 1);
+
+        // overlapped sstables are rewritten
+        write_keys(50..150)?;
+        write_keys(300..400)?;
+        while engine.inner.trigger_compaction()? {}
+        {
+            let state = engine.inner.state.read();
+            assert!(state.l0_sstables.is_empty());
+            assert!(state.level_sstables(1).iter().all(|id| !moved.contains(id)));
+        }
+        for id in &moved {
+            assert!(!engine.inner.path_of_sst(*id as usize).exists());
+        }
+        for i in (0..200).chain(300..400) {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tombstone_and_periodic_compaction() -> Result<()> {
+        let open = |path: &Path, leveled_options: LeveledCompactionOptions| {
+            let options = LsmOptions {
+                compaction_options: CompactionOptions::Leveled(leveled_options),
+                num_levels: 2,
+                ..LsmOptions::default()
+            };
+            LsmEngine::open(path, options)
+        };
+        let write = |engine: &LsmEngine| -> Result<()> {
+            for i in 0..100 {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            engine.force_flush()?;
+            for i in 0..80 {
+                engine.delete(format!("key_{:05}", i).as_bytes())?;
+            }
+            engine.force_flush()?;
+            while engine.inner.trigger_compaction()? {}
+            Ok(())
+        };
+        let level_tombstones = |engine: &LsmEngine, level: usize| -> Vec<u64> {
+            let state = engine.inner.state.read();
+            state
+                .level_sstables(level)
+                .iter()
+                .map(|id| state.sstables[id].properties().num_tombstones)
+                .collect()
+        };
+
+        // the tombstones are left in level 1 by the size triggers
+        let dir = tempdir()?;
+        let leveled_options = LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            ..LeveledCompactionOptions::default()
+        };
+        let engine = open(&dir.path().join("size"), leveled_options.clone())?;
+        write(&engine)?;
+        assert_eq!(level_tombstones(&engine, 1), vec![80]);
+
+        // the tombstone-dense sstable is moved into the bottom level and rewritten
+        let engine = open(&dir.path().join("tombstone"), LeveledCompactionOptions {
+            tombstone_compaction_ratio: 0.5,
+            ..leveled_options.clone()
+        })?;
+        write(&engine)?;
+        assert!(level_tombstones(&engine, 1).is_empty());
+        assert_eq!(level_tombstones(&engine, 2), vec![0]);
+        assert_eq!(get(&engine, 79)?, None);
+        assert_eq!(get(&engine, 80)?, Some(Bytes::from("value")));
+
+        // the bottom sstable is not picked if a reader can see its tombstones
+        let engine = open(&dir.path().join("reader"), leveled_options.clone())?;
+        for i in 0..100 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+        }
+        let snapshot = engine.inner.mvcc().latest_version();
+        engine.inner.mvcc().version.lock().1.add_reader(snapshot);
+        for i in 0..80 {
+            engine.delete(format!("key_{:05}", i).as_bytes())?;
+        }
+        engine.force_flush()?;
+        engine.force_full_compaction()?;
+        // the 100 versions read by the snapshot are kept
+        assert_eq!(level_tombstones(&engine, 2), vec![80]);
+        let state = engine.inner.state.read().clone();
+        let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+            tombstone_compaction_ratio: 0.4,
+            ..leveled_options.clone()
+        });
+        let watermark = engine.inner.mvcc().watermark();
+        assert!(
+            controller
+                .generate_compaction_task(&state, watermark)
+                .is_none()
+        );
+        engine.inner.mvcc().version.lock().1.remove_reader(snapshot);
+        let watermark = engine.inner.mvcc().watermark();
+        let task = controller.generate_compaction_task(&state, watermark);
+        let Some(CompactionTask::Leveled(task)) = task else {
+            panic!("the bottom sstable is not picked");
+        };
+        assert_eq!((task.upper_level, task.lower_level), (2, 2));
+        assert_eq!(task.upper_level_sstables, state.level_sstables(2).to_vec());
+
+        // sstables older than the period are rewritten, the oldest one first
+        let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+            periodic_compaction_seconds: 3600,
+            ..leveled_options
+        });
+        let creation_time = state.sstables[&state.level_sstables(2)[0]]
+            .properties()
+            .creation_time;
+        assert!(
+            controller
+                .generate_compaction_task_at(&state, watermark, creation_time + 10)
+                .is_none()
+        );
+        let task = controller.generate_compaction_task_at(&state, watermark, creation_time + 3600);
+        let Some(CompactionTask::Leveled(task)) = task else {
+            panic!("the old sstable is not picked");
+        };
+        assert_eq!((task.upper_level, task.lower_level), (2, 2));
+        assert!(task.is_lower_level_bottom);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_compaction() -> Result<()> {
+        let dir = tempdir()?;
+        let open = |path: &Path, seek_compaction: bool| {
+            let options = LsmOptions {
+                compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+                    level0_file_num_compaction_trigger: 1,
+                    ..LeveledCompactionOptions::default()
+                }),
+                num_levels: 2,
+                seek_compaction,
+                ..LsmOptions::default()
+            };
+            let engine = LsmEngine::open(path, options)?;
+            // odd keys in level 2, even keys in level 1
+            for i in (1..200).step_by(2) {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            engine.force_flush()?;
+            engine.force_full_compaction()?;
+            for i in (0..200).step_by(2) {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            engine.force_flush()?;
+            while engine.inner.trigger_compaction()? {}
+            Result::<LsmEngine>::Ok(engine)
+        };
+        let level_info = |engine: &LsmEngine, level: usize| {
+            let state = engine.inner.state.read();
+            let ids = state.level_sstables(level);
+            assert_eq!(ids.len(), 1);
+            state.sstables[&ids[0]].clone()
+        };
+
+        let engine = open(&dir.path().join("seek"), true)?;
+        let upper = level_info(&engine, 1);
+        let allowed_seeks = upper.allowed_seeks();
+        // lookups found in level 1 and those of keys out of its range are not charged
+        for i in (0..200).step_by(2).chain(200..210) {
+            get(&engine, i)?;
+        }
+        assert_eq!(upper.allowed_seeks(), allowed_seeks);
+
+        // level 1 is searched before finding the odd keys in level 2
+        for i in 0..allowed_seeks - 1 {
+            assert_eq!(
+                get(&engine, (i as usize % 100) * 2 + 1)?,
+                Some(Bytes::from("value"))
+            );
+        }
+        assert_eq!(upper.allowed_seeks(), 1);
+        assert!(!engine.inner.trigger_compaction()?);
+        get(&engine, 1)?;
+        while engine.inner.trigger_compaction()? {}
+        {
+            let state = engine.inner.state.read();
+            assert!(state.level_sstables(1).is_empty());
+            assert!(!state.sstables.contains_key(&upper.id()));
+        }
+        for i in 0..200 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+
+        // seeks are not charged without the switch
+        let engine = open(&dir.path().join("no_seek"), false)?;
+        let upper = level_info(&engine, 1);
+        let allowed_seeks = upper.allowed_seeks();
+        for i in 0..allowed_seeks {
+            get(&engine, (i as usize % 100) * 2 + 1)?;
+        }
+        assert_eq!(upper.allowed_seeks(), allowed_seeks);
+        while engine.inner.trigger_compaction()? {}
+        assert_eq!(level_info(&engine, 1).id(), upper.id());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+        for i in 0..200 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"v1")?;
+            if i == 99 {
+                engine.force_flush()?;
+            }
+        }
+        engine.force_flush()?;
+        engine.force_full_compaction()?;
+        let external_file = external_dir.path().join("a.sst");
+        write_external_file(&external_file, 300..350, "a")?;
+        engine.ingest_external_files(&[&external_file])?;
+        for i in 0..50 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"v2")?;
+        }
+        engine.force_flush()?;
+        // left in the wal
+        for i in 200..210 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"v3")?;
+        }
+        engine.delete(b"key_00005")?;
+        let (l0_sstables, levels) = {
+            let state = engine.inner.state.read();
+            (state.l0_sstables.clone(), state.levels.clone())
+        };
+        let latest_version = engine.inner.mvcc().latest_version();
+        drop(engine);
+
+        // files not in the manifest are removed
+        let orphan = dir.path().join("00999.sst");
+        std::fs::write(&orphan, b"orphan")?;
+
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+        assert!(!orphan.exists());
+        assert_eq!(engine.manifest_truncated_bytes(), 0);
+        assert_eq!(engine.inner.mvcc().latest_version(), latest_version);
+        let recovered = engine.inner.state.read().clone();
+        // the memtable replayed from the wal is flushed into level 0
+        assert_eq!(recovered.l0_sstables.len(), l0_sstables.len() + 1);
+        assert_eq!(&recovered.l0_sstables[1..], &l0_sstables[..]);
+        assert_eq!(recovered.levels, levels);
+        assert_eq!(recovered.sstables[&levels[5].1[0]].global_version(), None);
+        let ingested = levels[5].1[1];
+        assert!(recovered.sstables[&ingested].global_version().is_some());
+        let mut files: Vec<_> = dir
+            .path()
+            .read_dir()?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<_>>()?;
+        files.sort();
+        let mut expected: Vec<_> = recovered
+            .sstables
+            .keys()
+            .map(|id| format!("{:05}.sst", id))
+            .chain([
+                format!("{:05}.wal", recovered.memtable.id()),
+                "MANIFEST".to_string(),
+            ])
+            .collect();
+        expected.sort();
+        assert_eq!(files, expected);
+
+        for i in 0..350 {
+            let expected = match i {
+                5 => None,
+                0..50 => Some(Bytes::from("v2")),
+                50..200 => Some(Bytes::from("v1")),
+                200..210 => Some(Bytes::from("v3")),
+                300..350 => Some(Bytes::from(format!("a_{}", i))),
+                _ => None,
+            };
+            assert_eq!(get(&engine, i)?, expected);
+        }
+
+        // new ids and versions follow the recovered ones
+        engine.put(b"key_00000", b"v4")?;
+        engine.force_flush()?;
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("v4")));
+        let l0_sstables = engine.inner.state.read().l0_sstables.clone();
+        assert!(l0_sstables[0] > *recovered.sstables.keys().max().unwrap());
+
+        // the empty memtable is reused, and a partially written manifest record
+        // is dropped
+        let memtable_id = engine.inner.state.read().memtable.id();
+        drop(engine);
+        let mut manifest = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("MANIFEST"))?;
+        std::io::Write::write_all(&mut manifest, &[0, 0, 0, 16, 1, 2])?;
+        drop(manifest);
+        let engine = LsmEngine::open(dir.path(), LsmOptions::default())?;
+        assert_eq!(engine.manifest_truncated_bytes(), 6);
+        assert_eq!(engine.inner.state.read().memtable.id(), memtable_id);
+        assert_eq!(engine.inner.state.read().l0_sstables, l0_sstables);
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("v4")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_after_moving_ingested_sstable() -> Result<()> {
+        let dir = tempdir()?;
+        let external_dir = tempdir()?;
+        let open = |num_levels| {
+            let options = LsmOptions {
+                num_levels,
+                ..LsmOptions::default()
+            };
+            LsmEngine::open(dir.path(), options)
+        };
+
+        // the ingested sstable goes to the bottom level of the empty engine
+        let engine = open(3)?;
+        engine.put(b"key_00100", b"v1")?;
+        engine.force_flush()?;
+        let external_file = external_dir.path().join("a.sst");
+        write_external_file(&external_file, 0..50, "a")?;
+        engine.ingest_external_files(&[&external_file])?;
+        let ingested = engine.inner.state.read().level_sstables(3)[0];
+        let global_version = engine.inner.state.read().sstables[&ingested].global_version();
+        assert!(global_version.is_some());
+        drop(engine);
+
+        // with one more level, the ingested sstable is moved into the new bottom level
+        let engine = open(4)?;
+        engine.compact_range(None, None, &CompactRangeOptions::default())?;
+        let moved = engine.inner.state.read().level_sstables(4).to_vec();
+        assert!(moved.contains(&ingested));
+        let latest_version = engine.inner.mvcc().latest_version();
+        drop(engine);
+
+        let engine = open(4)?;
+        {
+            let state = engine.inner.state.read();
+            assert!(state.level_sstables(4).contains(&ingested));
+            assert_eq!(state.sstables[&ingested].global_version(), global_version);
+        }
+        assert_eq!(engine.inner.mvcc().latest_version(), latest_version);
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("a_0")));
+        assert_eq!(get(&engine, 100)?, Some(Bytes::from("v1")));
+        engine.put(b"key_00000", b"v2")?;
+        assert_eq!(get(&engine, 0)?, Some(Bytes::from("v2")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_synced_writes_survive_crash() -> Result<()> {
+        let fs = Arc::new(FaultInjectionFileSystem::new(
+            Arc::new(MemFileSystem::new()),
+        ));
+        let dir = Path::new("/db");
+        let open = || {
+            let options = LsmOptions {
+                fs: fs.clone(),
+                ..LsmOptions::default()
+            };
+            LsmEngine::open(dir, options)
+        };
+        let engine = open()?;
+        for i in 0..100 {
+            engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+        }
+        // the writes are only in the wal
+        drop(engine);
+        fs.drop_unsynced_data()?;
+
+        let engine = open()?;
+        for i in 0..100 {
+            assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_flush_is_not_published() -> Result<()> {
+        let dir = Path::new("/db");
+        for n in 0.. {
+            let fs = Arc::new(FaultInjectionFileSystem::new(
+                Arc::new(MemFileSystem::new()),
+            ));
+            let options = LsmOptions {
+                fs: fs.clone(),
+                ..LsmOptions::default()
+            };
+            let engine = LsmEngine::open(dir, options)?;
+            for i in 0..100 {
+                engine.put(format!("key_{:05}", i).as_bytes(), b"value")?;
+            }
+            fs.fail_syncs_after(Some(n));
+            if engine.force_flush().is_ok() {
+                assert_eq!(engine.inner.state.read().l0_sstables.len(), 1);
+                break;
+            }
+
+            // the memtable stays readable, no table is left behind
+            let state = engine.inner.state.read().clone();
+            assert!(state.l0_sstables.is_empty());
+            assert!(state.sstables.is_empty());
+            assert!(
+                fs.list(dir)?
+                    .iter()
+                    .all(|path| path.extension().is_none_or(|ext| ext != "sst"))
+            );
+            for i in 0..100 {
+                assert_eq!(get(&engine, i)?, Some(Bytes::from("value")));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_engine_crash() -> Result<()> {
+        let dir = Path::new("/db");
+        let open = |fs: &Arc<FaultInjectionFileSystem>| {
+            let options = LsmOptions {
+                fs: fs.clone(),
+                memtable_size: 512,
+                ..LsmOptions::default()
+            };
+            LsmEngine::open(dir, options)
+        };
+        // the engine crashes in writing the wal and the manifest, or in building
+        // the sstables of flush and compaction
+        run_crash_test(
+            |fs, acknowledged| {
+                let engine = open(fs)?;
+                for i in 0..40 {
+                    let keys: Vec<_> = (0..3).map(|j| format!("key_{:05}", i * 3 + j)).collect();
+                    if i % 2 == 0 {
+                        engine.put(keys[0].as_bytes(), keys[0].as_bytes())?;
+                        acknowledged.push(keys[0].clone());
+                    } else {
+                        let batch: Vec<_> = keys
+                            .iter()
+                            .map(|key| WriteBatchRecord::Put(key, key))
+                            .collect();
+                        engine.write_batch(&batch)?;
+                        acknowledged.extend(keys);
+                    }
+                }
+                engine.force_full_compaction()?;
+                Ok(())
+            },
+            |fs, acknowledged: &[String]| {
+                let engine = open(fs)?;
+                for key in acknowledged {
+                    assert_eq!(
+                        engine.get(key.as_bytes())?,
+                        Some(Bytes::copy_from_slice(key.as_bytes()))
+                    );
+                }
+                engine.put(b"key_99999", b"value")?;
+                assert_eq!(engine.get(b"key_99999")?, Some(Bytes::from("value")));
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_reopen_crash() -> Result<()> {
+        let dir = Path::new("/db");
+        let open = |fs: &Arc<FaultInjectionFileSystem>| {
+            let options = LsmOptions {
+                fs: fs.clone(),
+                ..LsmOptions::default()
+            };
+            LsmEngine::open(dir, options)
+        };
+        run_crash_test(
+            |fs, acknowledged| {
+                let engine = open(fs)?;
+                for round in 0..3 {
+                    for i in round * 10..round * 10 + 20 {
+                        let value = format!("value_{}", round);
+                        engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+                        acknowledged.push((i, round));
+                    }
+                    engine.force_flush()?;
+                    if round == 1 {
+                        engine.force_full_compaction()?;
+                    }
+                }
+                Ok(())
+            },
+            |fs, acknowledged: &[(usize, usize)]| {
+                // every acknowledged write survives the crash, a key may also have
+                // the value of a later write failed in syncing
+                let engine = open(fs)?;
+                let mut expected = std::collections::HashMap::new();
+                expected.extend(acknowledged.iter().copied());
+                for (i, round) in expected {
+                    let value = get(&engine, i)?;
+                    assert!(value.is_some_and(|value| value >= format!("value_{}", round)));
+                }
+
+                // the recovered engine works
+                engine.put(b"key_00100", b"value")?;
+                engine.force_flush()?;
+                drop(engine);
+                let engine = open(fs)?;
+                assert_eq!(get(&engine, 100)?, Some(Bytes::from("value")));
+                Ok(())
+            },
+        )
+    }
 }
@@ -12,41 +12,1239 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
+use anyhow::Context;
 use anyhow::Result;
+use anyhow::bail;
 use bytes::Bytes;
+use parking_lot::Mutex;
+use parking_lot::MutexGuard;
 use parking_lot::RwLock;
 
+use super::IngestedFile;
 use super::LsmEngineState;
 use super::LsmOptions;
+use super::Manifest;
+use super::ManifestRecord;
+use super::ManifestState;
 use super::WriteBatchRecord;
+use crate::base::KeySlice;
+use crate::base::VERSION_DEFAULT;
 use crate::base::Version;
+use crate::compact::CompactRangeOptions;
+use crate::compact::CompactRangeProgress;
+use crate::compact::CompactionController;
+use crate::compact::CompactionFilterIterator;
+use crate::compact::CompactionJob;
+use crate::compact::CompactionJobInput;
+use crate::compact::CompactionJobOutput;
+use crate::compact::CompactionOptions;
+use crate::compact::CompactionTask;
+use crate::compact::GcIterator;
+use crate::compact::LeveledCompactionController;
+use crate::compact::TieredCompactionController;
+use crate::compact::run_compaction_worker;
+use crate::compact::write_compaction_outputs;
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::IoPriority;
+use crate::iterator::MergeIterator;
+use crate::iterator::StorageIterator;
+use crate::memtable::Memtable;
 use crate::mvcc::MvccInner;
+use crate::table::BlockCache;
+use crate::table::FileObject;
+use crate::table::SsTable;
+use crate::table::SsTableBuilder;
+use crate::table::SsTableId;
+use crate::table::SsTableInfo;
+use crate::table::SsTableIterator;
+use crate::table::TableCache;
+use crate::wal::Wal;
 
 pub struct LsmEngineInner {
     pub state: Arc<RwLock<Arc<LsmEngineState>>>,
+    // serializes the changes of state structure, such as freezing memtable,
+    // flushing memtable and ingesting sstables.
+    // MUST be acquired after `mvcc.write_lock` if both are needed.
+    pub state_lock: Mutex<()>,
+    // only one compaction runs at a time, ingestion also waits for it.
+    // MUST be acquired before `mvcc.write_lock` and `state_lock`.
+    compaction_lock: Mutex<()>,
+    compaction_controller: CompactionController,
     pub mvcc: MvccInner,
+
+    path: PathBuf,
+    options: Arc<LsmOptions>,
+    block_cache: Arc<BlockCache>,
+    pub(crate) table_cache: TableCache,
+    // id of memtables and sstables, a memtable is flushed to the sstable with the same id
+    next_id: AtomicUsize,
+    manifest: Manifest,
+    // bytes dropped from the tail of the manifest when the engine is opened
+    manifest_truncated_bytes: usize,
+    // sstables removed by compaction, their files are deleted
+    // when no state referring to them is held by readers
+    obsolete_sstables: Mutex<Vec<Arc<SsTableInfo>>>,
+    // the error stopping the background compaction, writes fail after it is set
+    background_error: Mutex<Option<Arc<anyhow::Error>>>,
 }
 
 impl LsmEngineInner {
-    // pub fn open(path: impl AsRef<Path>, options: LsmOptions) -> Result<Self> {
-    // let state = LsmEngineState::create();
-    //
-    // Ok()
-    // }
+    pub fn open(path: impl AsRef<Path>, options: LsmOptions) -> Result<Self> {
+        if options.use_mmap_reads && options.use_direct_reads {
+            bail!("use_mmap_reads and use_direct_reads can not be both enabled");
+        }
+        if options.compaction_worker.is_some() && options.compaction_filter.is_some() {
+            bail!("compaction_filter can not be used with compaction_worker");
+        }
+        let path = path.as_ref();
+        options
+            .fs
+            .create_dir_all(path)
+            .with_context(|| format!("create engine directory {:?}", path))?;
+
+        let fs = options.fs.as_ref();
+        let (manifest, recovery) = Manifest::open(fs, path, options.truncate_corrupted_manifest)?;
+        let recovered = ManifestState::fold(&recovery.records, options.num_levels)
+            .with_context(|| format!("recover manifest of {:?}", path))?;
+        Self::remove_obsolete_files(fs, path, &recovered)?;
+        let mut next_id = recovered.max_id.map_or(0, |id| id + 1);
+        let mut latest_version = VERSION_DEFAULT;
+
+        let mut sstables = HashMap::new();
+        for id in recovered.sstables() {
+            let sst_path = path.join(format!("{:05}.sst", id));
+            let mut table = SsTable::open(
+                id,
+                None,
+                FileObject::open(fs, &sst_path, options.file_options())?,
+            )
+            .with_context(|| format!("open sstable {:?}", sst_path))?;
+            if let Some(global_version) = recovered.global_versions.get(&id) {
+                table.set_global_version(*global_version);
+            }
+            let info = table.info();
+            latest_version = latest_version.max(info.global_version().unwrap_or(info.max_version));
+            sstables.insert(id, Arc::new(info));
+        }
+
+        // the memtables not flushed are replayed from their wals and flushed after
+        // the engine is opened, the newest one is reused if it is empty
+        let mut current = None;
+        let mut imm_memtables = Vec::new();
+        for (idx, id) in recovered.memtables.iter().enumerate() {
+            let wal_path = Self::wal_path(path, *id);
+            let entries = if fs.exists(&wal_path) {
+                Wal::recover(fs, &wal_path)?
+            } else {
+                Vec::new()
+            };
+            if entries.is_empty() {
+                if fs.exists(&wal_path) {
+                    fs.remove(&wal_path)?;
+                }
+                if idx + 1 == recovered.memtables.len() {
+                    current = Some(Self::create_memtable(path, &options, *id)?);
+                }
+                continue;
+            }
+            let data: Vec<_> = entries
+                .iter()
+                .map(|(key, value)| (key.to_key_slice(), value.as_ref()))
+                .collect();
+            let memtable = Memtable::new(*id);
+            memtable.write_batch(&data)?;
+            latest_version = entries
+                .iter()
+                .map(|(key, _)| key.version())
+                .fold(latest_version, Version::max);
+            imm_memtables.insert(0, Arc::new(memtable));
+        }
+        let memtable = match current {
+            Some(memtable) => memtable,
+            None => {
+                let id = next_id;
+                next_id += 1;
+                let memtable = Self::create_memtable(path, &options, id)?;
+                manifest.add_record(ManifestRecord::NewMemtable(id))?;
+                memtable
+            }
+        };
+
+        let mut state = LsmEngineState::create(memtable, options.num_levels);
+        state.imm_memtables = imm_memtables;
+        state.l0_sstables = recovered.l0_sstables;
+        for ((_, ids), mut recovered_ids) in state.levels.iter_mut().zip(recovered.levels) {
+            recovered_ids.sort_by(|a, b| sstables[a].first_key().cmp(sstables[b].first_key()));
+            *ids = recovered_ids;
+        }
+        state.sstables = sstables;
+
+        let engine = Self {
+            state: Arc::new(RwLock::new(Arc::new(state))),
+            state_lock: Mutex::new(()),
+            compaction_lock: Mutex::new(()),
+            compaction_controller: CompactionController::new(&options.compaction_options),
+            mvcc: MvccInner::new(latest_version),
+            path: path.to_path_buf(),
+            block_cache: Arc::new(BlockCache::new(options.block_cache_num)),
+            table_cache: TableCache::new(options.max_open_files),
+            options: Arc::new(options),
+            next_id: AtomicUsize::new(next_id),
+            manifest,
+            manifest_truncated_bytes: recovery.truncated_bytes,
+            obsolete_sstables: Mutex::new(Vec::new()),
+            background_error: Mutex::new(None),
+        };
+        engine.flush_imm_memtables(&engine.state_lock.lock())?;
+        Ok(engine)
+    }
+
+    // remove the files not in the manifest, which are left by unfinished flushes,
+    // compactions and ingestions, or compacted sstables not deleted before closing
+    fn remove_obsolete_files(
+        fs: &dyn FileSystem,
+        path: &Path,
+        recovered: &ManifestState,
+    ) -> Result<()> {
+        let live_sstables: HashSet<SsTableId> = recovered.sstables().collect();
+        for file in fs.list(path)? {
+            let stem = file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("");
+            let live = match file.extension().and_then(|ext| ext.to_str()) {
+                Some("sst") => stem
+                    .parse::<SsTableId>()
+                    .is_ok_and(|id| live_sstables.contains(&id)),
+                Some("wal") => stem
+                    .parse::<usize>()
+                    .is_ok_and(|id| recovered.memtables.contains(&id)),
+                _ => continue,
+            };
+            if !live {
+                fs.remove(&file)?;
+            }
+        }
+        fs.sync_dir(path)
+    }
 
     pub fn mvcc(&self) -> &MvccInner {
         &self.mvcc
     }
 
+    pub fn options(&self) -> &LsmOptions {
+        &self.options
+    }
+
+    pub fn manifest_truncated_bytes(&self) -> usize {
+        self.manifest_truncated_bytes
+    }
+
+    pub fn compaction_controller(&self) -> &CompactionController {
+        &self.compaction_controller
+    }
+
+    fn fs(&self) -> &dyn FileSystem {
+        self.options.fs.as_ref()
+    }
+
+    pub fn path_of_sst(&self, id: usize) -> PathBuf {
+        self.path.join(format!("{:05}.sst", id))
+    }
+
+    pub fn path_of_wal(&self, id: usize) -> PathBuf {
+        Self::wal_path(&self.path, id)
+    }
+
+    fn wal_path(path: &Path, id: usize) -> PathBuf {
+        path.join(format!("{:05}.wal", id))
+    }
+
+    fn next_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn create_memtable(path: &Path, options: &LsmOptions, id: usize) -> Result<Memtable> {
+        if !options.enable_wal {
+            return Ok(Memtable::new(id));
+        }
+        let memtable =
+            Memtable::create_with_wal(id, options.fs.as_ref(), Self::wal_path(path, id))?;
+        // the wal must be found after a crash once the writes in it are acknowledged
+        options.fs.sync_dir(path)?;
+        Ok(memtable)
+    }
+
+    // return the opened sstable from table cache, open it if it is not cached
+    fn table(&self, info: &SsTableInfo) -> Result<Arc<SsTable>> {
+        self.table_cache.get_or_open(info.id(), || {
+            self.open_table(
+                info,
+                Some(self.block_cache.clone()),
+                self.options.file_options(),
+            )
+        })
+    }
+
+    // return the sstable read by compaction. with direct I/O for compaction, it is
+    // opened again without the block cache, so that compaction reads go around
+    // both the page cache and the block cache.
+    fn compaction_input_table(&self, info: &SsTableInfo) -> Result<Arc<SsTable>> {
+        if !self.options.use_direct_io_for_flush_and_compaction {
+            return self.table(info);
+        }
+        let table = self.open_table(info, None, self.options.compaction_input_file_options())?;
+        Ok(Arc::new(table))
+    }
+
+    fn open_table(
+        &self,
+        info: &SsTableInfo,
+        block_cache: Option<Arc<BlockCache>>,
+        file_options: FileOptions,
+    ) -> Result<SsTable> {
+        let path = self.path_of_sst(info.id() as usize);
+        let mut table = SsTable::open(
+            info.id(),
+            block_cache,
+            FileObject::open(self.fs(), &path, file_options)?,
+        )
+        .with_context(|| format!("open sstable {:?}", path))?;
+        if let Some(global_version) = info.global_version() {
+            table.set_global_version(global_version);
+        }
+        Ok(table)
+    }
+
+    fn sync_dir(&self) -> Result<()> {
+        self.fs().sync_dir(&self.path)
+    }
+
     pub fn get_with_version(&self, key: &[u8], version: Version) -> Result<Option<Bytes>> {
+        let state = self.state.read().clone();
+
+        // an empty value means the key is deleted
+        let non_deleted = |value: Bytes| if value.is_empty() { None } else { Some(value) };
+
+        // data in upper levels is always newer than lower levels, so the first
+        // version found is the latest one
+        if let Some(value) = state.memtable.get(key, version) {
+            return Ok(non_deleted(value));
+        }
+        for memtable in &state.imm_memtables {
+            if let Some(value) = memtable.get(key, version) {
+                return Ok(non_deleted(value));
+            }
+        }
+
+        let level_sstables = state.levels.iter().filter_map(|(_, ids)| {
+            let idx = ids.partition_point(|id| state.sstables[id].last_key().key_ref() < key);
+            ids.get(idx)
+        });
+        // like leveldb, if more than one sstable is searched, the first one
+        // is charged a seek
+        let mut first_searched: Option<&SsTableInfo> = None;
+        let mut charged = !self.options.seek_compaction;
+        for id in state.l0_sstables.iter().chain(level_sstables) {
+            let info = &state.sstables[id];
+            if !charged
+                && info.contains_key(key)
+                && info.overlaps_versions(VERSION_DEFAULT, version)
+            {
+                match first_searched {
+                    Some(first) => {
+                        first.record_seek();
+                        charged = true;
+                    }
+                    None => first_searched = Some(info),
+                }
+            }
+            if let Some(value) = self.get_from_table(info, key, version)? {
+                return Ok(non_deleted(value));
+            }
+        }
+
         Ok(None)
     }
 
+    // get the values of keys, the blocks of keys in the same sstable are read
+    // with one batched read
+    pub fn multi_get_with_version(
+        &self,
+        keys: &[&[u8]],
+        version: Version,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let state = self.state.read().clone();
+
+        // None means the key is not found yet, Some(empty value) means it is deleted
+        let mut values: Vec<Option<Bytes>> = Vec::with_capacity(keys.len());
+        for key in keys {
+            let value = std::iter::once(&state.memtable)
+                .chain(state.imm_memtables.iter())
+                .find_map(|memtable| memtable.get(key, version));
+            values.push(value);
+        }
+
+        // search sstables level by level, only the keys not found are searched in lower levels
+        let num_l0_sstables = state.l0_sstables.len();
+        for level in 0..num_l0_sstables + state.levels.len() {
+            let mut search: HashMap<SsTableId, Vec<usize>> = HashMap::new();
+            for (idx, key) in keys.iter().enumerate() {
+                if values[idx].is_some() {
+                    continue;
+                }
+                if level < num_l0_sstables {
+                    search
+                        .entry(state.l0_sstables[level])
+                        .or_default()
+                        .push(idx);
+                    continue;
+                }
+                let (_, ids) = &state.levels[level - num_l0_sstables];
+                let pos = ids.partition_point(|id| state.sstables[id].last_key().key_ref() < *key);
+                if pos < ids.len() {
+                    search.entry(ids[pos]).or_default().push(idx);
+                }
+            }
+            for (id, idxs) in &search {
+                let info = &state.sstables[id];
+                self.prefetch_blocks(info, idxs.iter().map(|idx| keys[*idx]), version)?;
+                for idx in idxs {
+                    values[*idx] = self.get_from_table(info, keys[*idx], version)?;
+                }
+            }
+        }
+
+        Ok(values
+            .into_iter()
+            .map(|value| value.filter(|value| !value.is_empty()))
+            .collect())
+    }
+
+    // read the blocks which may contain the keys into the block cache at once
+    fn prefetch_blocks<'a>(
+        &self,
+        info: &SsTableInfo,
+        keys: impl Iterator<Item = &'a [u8]>,
+        version: Version,
+    ) -> Result<()> {
+        if !info.overlaps_versions(VERSION_DEFAULT, version) {
+            return Ok(());
+        }
+        let keys: Vec<&[u8]> = keys.filter(|key| info.contains_key(key)).collect();
+        if keys.len() < 2 {
+            return Ok(());
+        }
+        let table = self.table(info)?;
+        let mut blocks = Vec::new();
+        for key in keys {
+            if !table.may_contain(key) {
+                continue;
+            }
+            if let Some(block) = table.find_block(KeySlice::from_slice(key, VERSION_DEFAULT))? {
+                blocks.push(block);
+            }
+        }
+        blocks.sort();
+        blocks.dedup();
+        if blocks.len() > 1 {
+            table.read_blocks(&blocks)?;
+        }
+        Ok(())
+    }
+
+    // return the value of the latest version of `key` not greater than `version` in the table
+    fn get_from_table(
+        &self,
+        info: &SsTableInfo,
+        key: &[u8],
+        version: Version,
+    ) -> Result<Option<Bytes>> {
+        if !info.contains_key(key) || !info.overlaps_versions(VERSION_DEFAULT, version) {
+            return Ok(None);
+        }
+        let table = self.table(info)?;
+        if !table.may_contain(key) {
+            return Ok(None);
+        }
+
+        let mut iter = SsTableIterator::create_and_seek_to_key_with_versions(
+            table,
+            KeySlice::from_slice(key, VERSION_DEFAULT),
+            VERSION_DEFAULT,
+            version,
+        )?;
+        let mut value = None;
+        while iter.is_valid() && iter.key().key_ref() == key {
+            value = Some(Bytes::copy_from_slice(iter.value()));
+            iter.next()?;
+        }
+        Ok(value)
+    }
+
+    pub fn set_background_error(&self, err: anyhow::Error) {
+        self.background_error.lock().get_or_insert(Arc::new(err));
+    }
+
+    // return the error of the background compaction if it has failed
+    pub fn check_background_error(&self) -> Result<()> {
+        if let Some(err) = self.background_error.lock().as_ref() {
+            bail!("background compaction failed: {:?}", err);
+        }
+        Ok(())
+    }
+
     // write batch records, return the committed version
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<Version> {
-        Ok(0)
+        self.check_background_error()?;
+        let _write_lock = self.mvcc.write_lock.lock();
+        let version = self.mvcc.latest_version() + 1;
+
+        let mut data = Vec::with_capacity(batch.len());
+        for record in batch {
+            let (key, value) = match record {
+                WriteBatchRecord::Put(key, value) => (key.as_ref(), value.as_ref()),
+                WriteBatchRecord::Del(key) => (key.as_ref(), &[][..]),
+            };
+            if key.is_empty() {
+                bail!("key MUST not be empty");
+            }
+            data.push((KeySlice::from_slice(key, version), value));
+        }
+
+        let memtable = self.state.read().memtable.clone();
+        memtable.write_batch(&data)?;
+        if self.options.sync_wal {
+            memtable.sync_wal()?;
+        }
+        self.mvcc.update_latest_version(version);
+
+        if memtable.size() >= self.options.memtable_size {
+            let state_lock = self.state_lock.lock();
+            self.freeze_memtable(&state_lock)?;
+            self.flush_imm_memtables(&state_lock)?;
+        }
+
+        Ok(version)
+    }
+
+    // freeze the current memtable and flush all the immutable memtables
+    pub fn force_flush(&self) -> Result<()> {
+        let _write_lock = self.mvcc.write_lock.lock();
+        let state_lock = self.state_lock.lock();
+        self.freeze_memtable(&state_lock)?;
+        self.flush_imm_memtables(&state_lock)
+    }
+
+    // create a new memtable and turn the current one into an immutable memtable,
+    // do nothing if the current memtable is empty
+    fn freeze_memtable(&self, _state_lock: &MutexGuard<()>) -> Result<()> {
+        if self.state.read().memtable.is_empty() {
+            return Ok(());
+        }
+
+        let id = self.next_id();
+        let memtable = Arc::new(Self::create_memtable(&self.path, &self.options, id)?);
+        let old_memtable = {
+            let mut guard = self.state.write();
+            let mut state = guard.as_ref().clone();
+            let old_memtable = std::mem::replace(&mut state.memtable, memtable);
+            state.imm_memtables.insert(0, old_memtable.clone());
+            *guard = Arc::new(state);
+            old_memtable
+        };
+        old_memtable.sync_wal()?;
+        self.manifest.add_record(ManifestRecord::NewMemtable(id))?;
+
+        Ok(())
+    }
+
+    fn flush_imm_memtables(&self, state_lock: &MutexGuard<()>) -> Result<()> {
+        while self.flush_earliest_imm_memtable(state_lock)? {}
+        Ok(())
+    }
+
+    // flush the earliest immutable memtable into level 0, return false if there is none
+    fn flush_earliest_imm_memtable(&self, _state_lock: &MutexGuard<()>) -> Result<bool> {
+        let Some(memtable) = self.state.read().imm_memtables.last().cloned() else {
+            return Ok(false);
+        };
+
+        let id = memtable.id();
+        let mut builder = SsTableBuilder::create_with_options(&self.options)?;
+        builder.set_io_priority(IoPriority::High);
+        memtable.flush(&mut builder)?;
+        // the table is published only after the manifest knows it, the same as
+        // compaction, a failed flush removes the table and keeps the memtable
+        let result = builder
+            .build(
+                id as SsTableId,
+                Some(self.block_cache.clone()),
+                self.path_of_sst(id),
+            )
+            .and_then(|table| {
+                self.sync_dir()?;
+                self.manifest.add_record(ManifestRecord::Flush(id))?;
+                Ok(table)
+            });
+        let table = match result {
+            Ok(table) => table,
+            Err(err) => {
+                let _ = self.fs().remove(&self.path_of_sst(id));
+                return Err(err);
+            }
+        };
+        let info = Arc::new(table.info());
+        self.table_cache.insert(Arc::new(table));
+
+        {
+            let mut guard = self.state.write();
+            let mut state = guard.as_ref().clone();
+            state.imm_memtables.pop();
+            state.l0_sstables.insert(0, info.id());
+            state.sstables.insert(info.id(), info);
+            *guard = Arc::new(state);
+        }
+
+        if self.options.enable_wal {
+            self.fs().remove(&self.path_of_wal(id))?;
+        }
+        Ok(true)
+    }
+
+    // ingest external sstables written by `SstFileWriter`, the keys in these tables are
+    // assigned one global version which is newer than all the data in the engine.
+    pub fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> Result<()> {
+        // validate files before locking the engine
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let table = SsTable::open(
+                0,
+                None,
+                FileObject::open(self.fs(), path, FileOptions::default())?,
+            )
+            .with_context(|| format!("open external sstable {:?}", path))?;
+            if table.max_version() != VERSION_DEFAULT {
+                bail!("external sstable {:?} contains versioned keys", path);
+            }
+            files.push((
+                path,
+                table.first_key().key_ref().to_vec(),
+                table.last_key().key_ref().to_vec(),
+            ));
+        }
+        if files.is_empty() {
+            return Ok(());
+        }
+        files.sort_by(|a, b| a.1.cmp(&b.1));
+        for pair in files.windows(2) {
+            if pair[0].2 >= pair[1].1 {
+                bail!(
+                    "external sstables {:?} and {:?} overlap",
+                    pair[0].0,
+                    pair[1].0
+                );
+            }
+        }
+
+        // no write can be done until the ingestion is finished, and the level picked
+        // for the files must not be changed by compaction
+        let _compaction_lock = self.compaction_lock.lock();
+        let _write_lock = self.mvcc.write_lock.lock();
+        let state_lock = self.state_lock.lock();
+
+        // ingested keys are newer than data in memtables, flush the overlapped memtables first
+        let state = self.state.read().clone();
+        let overlaps_memtables = files.iter().any(|(_, first, last)| {
+            std::iter::once(&state.memtable)
+                .chain(state.imm_memtables.iter())
+                .any(|memtable| memtable.overlaps(first, last))
+        });
+        if overlaps_memtables {
+            self.freeze_memtable(&state_lock)?;
+            self.flush_imm_memtables(&state_lock)?;
+        }
+
+        let global_version = self.mvcc.latest_version() + 1;
+        let state = self.state.read().clone();
+        let mut ingested = Vec::with_capacity(files.len());
+        let mut tables = Vec::with_capacity(files.len());
+        for (path, first, last) in &files {
+            let id = self.next_id();
+            let sst_path = self.path_of_sst(id);
+            let result = self.link_or_copy(path, &sst_path).and_then(|_| {
+                SsTable::open(
+                    id as SsTableId,
+                    Some(self.block_cache.clone()),
+                    FileObject::open(self.fs(), &sst_path, self.options.file_options())?,
+                )
+            });
+            let mut table = match result {
+                Ok(table) => table,
+                Err(err) => {
+                    // remove the files already linked
+                    for file in ingested
+                        .iter()
+                        .map(|file: &IngestedFile| file.id)
+                        .chain([id])
+                    {
+                        let _ = self.fs().remove(&self.path_of_sst(file));
+                    }
+                    return Err(err);
+                }
+            };
+            table.set_global_version(global_version);
+
+            ingested.push(IngestedFile {
+                id,
+                level: Self::pick_ingestion_level(&state, first, last),
+                global_version,
+            });
+            tables.push(Arc::new(table));
+        }
+        self.sync_dir()?;
+
+        // all the files are installed with one manifest record
+        self.manifest
+            .add_record(ManifestRecord::Ingestion(ingested.clone()))?;
+        {
+            let mut guard = self.state.write();
+            let mut state = guard.as_ref().clone();
+            for (file, table) in ingested.iter().zip(tables) {
+                if file.level == 0 {
+                    state.l0_sstables.insert(0, table.id());
+                } else {
+                    let ids = &mut state.levels[file.level - 1].1;
+                    let idx = ids.partition_point(|id| {
+                        state.sstables[id].first_key().key_ref() < table.first_key().key_ref()
+                    });
+                    ids.insert(idx, table.id());
+                }
+                state.sstables.insert(table.id(), Arc::new(table.info()));
+                self.table_cache.insert(table);
+            }
+            *guard = Arc::new(state);
+        }
+        self.mvcc.update_latest_version(global_version);
+
+        Ok(())
+    }
+
+    // keys of the ingested table are newer than all the data in the engine, so it must be put
+    // above all the tables it overlaps: the lowest level where no table in this level and
+    // the upper levels overlaps it.
+    fn pick_ingestion_level(state: &LsmEngineState, first: &[u8], last: &[u8]) -> usize {
+        let mut level = 0;
+        for check_level in 0..=state.levels.len() {
+            if state.level_overlaps(check_level, first, last) {
+                break;
+            }
+            level = check_level;
+        }
+        level
+    }
+
+    fn link_or_copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let fs = self.fs();
+        if fs.hard_link(from, to).is_ok() {
+            return Ok(());
+        }
+        let copy = || -> Result<()> {
+            let data = fs.read_all(from)?;
+            let mut file = fs.create(to, FileOptions::default())?;
+            match &self.options.rate_limiter {
+                Some(rate_limiter) => rate_limiter.append(file.as_mut(), &data, IoPriority::Low)?,
+                None => file.append(&data)?,
+            }
+            file.sync()
+        };
+        copy().with_context(|| format!("copy external sstable {:?} to {:?}", from, to))
+    }
+
+    // run a compaction task picked by the configured strategy,
+    // return false if there is nothing to compact
+    pub fn trigger_compaction(&self) -> Result<bool> {
+        let compaction_lock = self.compaction_lock.lock();
+        let task = self
+            .compaction_controller
+            .generate_compaction_task(&self.state.read(), self.mvcc.watermark());
+        match task {
+            Some(task) => {
+                self.run_compaction(&compaction_lock, task)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // compact all the sstables into the bottom level
+    pub fn force_full_compaction(&self) -> Result<()> {
+        let compaction_lock = self.compaction_lock.lock();
+        let task = {
+            let state = self.state.read();
+            CompactionTask::Full {
+                l0_sstables: state.l0_sstables.clone(),
+                levels: state.levels.clone(),
+            }
+        };
+        self.run_compaction(&compaction_lock, task)
+    }
+
+    // compact all the sstables overlapping [start, end] into the bottom level,
+    // None means unbounded. memtables are flushed first, so that all the data
+    // written before is compacted.
+    pub fn compact_range(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: &CompactRangeOptions,
+    ) -> Result<()> {
+        if let CompactionOptions::Fifo(_) = &self.options.compaction_options {
+            bail!("compact_range is not supported by fifo compaction, which only drops sstables");
+        }
+        self.force_flush()?;
+
+        if let CompactionOptions::Tiered(_) = &self.options.compaction_options {
+            // the overlapping sorted runs are merged into the oldest one at once
+            let compaction_lock = self.compaction_lock.lock();
+            let task = {
+                let state = self.state.read();
+                TieredCompactionController::range_task(&state, |id| {
+                    Self::overlaps_range(&state, id, start, end)
+                })
+            };
+            let input_sstables = task.as_ref().map_or(0, |task| task.input_sstables().len());
+            if let Some(task) = task {
+                self.run_compaction(&compaction_lock, task)?;
+            }
+            if let Some(progress) = &options.progress {
+                progress(&CompactRangeProgress {
+                    level: 0,
+                    num_levels: self.options.num_levels,
+                    input_sstables,
+                });
+            }
+            return Ok(());
+        }
+
+        let exclusive_lock = options
+            .exclusive_manual_compaction
+            .then(|| self.compaction_lock.lock());
+        let num_levels = self.options.num_levels;
+        for level in 0..num_levels {
+            // the overlapped sstables are picked again for each level, since
+            // automatic compactions may change the levels if not exclusive
+            let compact_level = |compaction_lock: &MutexGuard<()>| -> Result<usize> {
+                let task = Self::compact_range_task(&self.state.read(), level, start, end);
+                let Some(task) = task else {
+                    return Ok(0);
+                };
+                let input_sstables = task.input_sstables().len();
+                self.run_compaction(compaction_lock, task)?;
+                Ok(input_sstables)
+            };
+            let input_sstables = match &exclusive_lock {
+                Some(compaction_lock) => compact_level(compaction_lock)?,
+                None => compact_level(&self.compaction_lock.lock())?,
+            };
+            if let Some(progress) = &options.progress {
+                progress(&CompactRangeProgress {
+                    level,
+                    num_levels,
+                    input_sstables,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // compact the sstables of `level` overlapping [start, end] into the next level
+    fn compact_range_task(
+        state: &LsmEngineState,
+        level: usize,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Option<CompactionTask> {
+        let overlaps = |id: &SsTableId| Self::overlaps_range(state, id, start, end);
+        let sstables: Vec<SsTableId> = if level == 0 {
+            // level 0 sstables are compacted from the oldest one, up to the newest
+            // one overlapping the range, since they overlap each other
+            let pos = state.l0_sstables.iter().position(overlaps)?;
+            state.l0_sstables[pos..].to_vec()
+        } else {
+            state
+                .level_sstables(level)
+                .iter()
+                .filter(|id| overlaps(id))
+                .copied()
+                .collect()
+        };
+        if sstables.is_empty() {
+            return None;
+        }
+        Some(LeveledCompactionController::task(
+            state,
+            level,
+            sstables,
+            level + 1,
+        ))
+    }
+
+    fn overlaps_range(
+        state: &LsmEngineState,
+        id: &SsTableId,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> bool {
+        let info = &state.sstables[id];
+        start.is_none_or(|start| start <= info.last_key().key_ref())
+            && end.is_none_or(|end| info.first_key().key_ref() <= end)
+    }
+
+    // merge the input sstables of `task` into new sstables, then replace the inputs
+    // with them in manifest and state
+    fn run_compaction(
+        &self,
+        _compaction_lock: &MutexGuard<()>,
+        task: CompactionTask,
+    ) -> Result<()> {
+        if task.input_sstables().is_empty() {
+            return Ok(());
+        }
+
+        // a trivial move keeps the input sstables as the outputs, nothing is read or
+        // written. the compaction filter has to see the data, so it disables moves.
+        let trivial_move =
+            self.options.compaction_filter.is_none() && task.is_trivial_move(&self.state.read());
+        let (outputs, infos) = if trivial_move {
+            let state = self.state.read();
+            let infos: Vec<Arc<SsTableInfo>> = task
+                .input_sstables()
+                .iter()
+                .map(|id| {
+                    let info = state.sstables[id].clone();
+                    // the lookups charged in the upper level are not in the moved level
+                    info.reset_allowed_seeks();
+                    info
+                })
+                .collect();
+            (Vec::new(), infos)
+        } else {
+            let outputs = if task.is_drop() {
+                Vec::new()
+            } else {
+                self.compact(&task)?
+            };
+            let infos = outputs.iter().map(|table| Arc::new(table.info())).collect();
+            (outputs, infos)
+        };
+        let output_ids: Vec<usize> = infos.iter().map(|info| info.id() as usize).collect();
+
+        let state_lock = self.state_lock.lock();
+        let result = self.sync_dir().and_then(|_| {
+            self.manifest
+                .add_record(ManifestRecord::Compaction(task.clone(), output_ids.clone()))
+        });
+        if let Err(err) = result {
+            if !trivial_move {
+                for id in &output_ids {
+                    let _ = self.fs().remove(&self.path_of_sst(*id));
+                }
+            }
+            return Err(err);
+        }
+        for table in outputs {
+            self.table_cache.insert(table);
+        }
+        let mut removed = {
+            let mut guard = self.state.write();
+            let mut state = guard.as_ref().clone();
+            let removed = state.apply_compaction(&task, infos);
+            *guard = Arc::new(state);
+            removed
+        };
+        drop(state_lock);
+        // moved sstables are still in use
+        removed.retain(|info| !output_ids.contains(&(info.id() as usize)));
+
+        self.obsolete_sstables.lock().extend(removed);
+        self.purge_obsolete_sstables()
+    }
+
+    // merge the input sstables, the newer data wins if the same key and version
+    // are in more than one sstable
+    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+        let state = self.state.read().clone();
+        let tables = task
+            .input_sstables()
+            .iter()
+            .map(|id| self.compaction_input_table(&state.sstables[id]))
+            .collect::<Result<Vec<_>>>()?;
+
+        // versions above the watermark are kept for the readers, and are never
+        // passed to the compaction filter
+        let watermark = self.mvcc.watermark();
+        if let Some(worker) = &self.options.compaction_worker {
+            return self.compact_in_worker(worker, task, &state, watermark);
+        }
+
+        // a level 0 sstable is a sorted run by itself, so level 0 output is not split
+        if task.output_level() == 0 {
+            return self.subcompact(task, &tables, watermark, None, None);
+        }
+        let input_size: usize = tables.iter().map(|table| table.table_size()).sum();
+        let num_subcompactions = std::cmp::min(
+            self.options.max_subcompactions,
+            input_size.div_ceil(self.options.target_file_size),
+        );
+        let boundaries = Self::subcompaction_boundaries(&tables, num_subcompactions);
+        if boundaries.is_empty() {
+            return self.subcompact(task, &tables, watermark, None, None);
+        }
+
+        // key ranges split by the boundaries are compacted in parallel, each of them
+        // writes its own sstables, which never overlap the ones of other ranges
+        let results: Vec<Result<Vec<Arc<SsTable>>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..=boundaries.len())
+                .map(|i| {
+                    let start = i.checked_sub(1).map(|i| boundaries[i].as_slice());
+                    let end = boundaries.get(i).map(|key| key.as_slice());
+                    let tables = &tables;
+                    scope.spawn(move || self.subcompact(task, tables, watermark, start, end))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("subcompaction panicked")))
+                })
+                .collect()
+        });
+
+        let mut outputs = Vec::new();
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(tables) => outputs.extend(tables),
+                Err(err) => error = error.or(Some(err)),
+            }
+        }
+        if let Some(err) = error {
+            for table in outputs {
+                let _ = self.fs().remove(&self.path_of_sst(table.id() as usize));
+            }
+            return Err(err);
+        }
+        Ok(outputs)
+    }
+
+    // run the compaction in a worker process, then rename the outputs after new ids
+    fn compact_in_worker(
+        &self,
+        worker: &Path,
+        task: &CompactionTask,
+        state: &LsmEngineState,
+        watermark: Version,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let job = CompactionJob {
+            job_id: self.next_id() as u64,
+            task: task.clone(),
+            inputs: task
+                .input_sstables()
+                .iter()
+                .map(|id| CompactionJobInput {
+                    path: self.path_of_sst(*id as usize),
+                    global_version: state.sstables[id].global_version(),
+                })
+                .collect(),
+            output_dir: self.path.clone(),
+            watermark,
+            block_size: self.options.block_size,
+            index_partition_size: self.options.index_partition_size,
+            target_file_size: self.options.target_file_size,
+            readahead_blocks: self.options.compaction_readahead_blocks,
+            use_direct_io: self.options.use_direct_io_for_flush_and_compaction,
+            rate_bytes_per_sec: self
+                .options
+                .rate_limiter
+                .as_ref()
+                .map(|rate_limiter| rate_limiter.rate_bytes_per_sec()),
+            table_properties_collectors: self
+                .options
+                .table_properties_collector_factories
+                .iter()
+                .map(|factory| factory.name().to_string())
+                .collect(),
+        };
+        let outputs = run_compaction_worker(worker, &job)?;
+
+        let mut tables = Vec::new();
+        // outputs renamed after new ids, including the ones failed to open
+        let mut renamed = Vec::new();
+        let mut install = |output: &CompactionJobOutput| -> Result<()> {
+            let id = self.next_id();
+            let path = self.path_of_sst(id);
+            self.fs().rename(&output.path, &path)?;
+            renamed.push(path.clone());
+            let table = SsTable::open(
+                id as SsTableId,
+                Some(self.block_cache.clone()),
+                FileObject::open(self.fs(), &path, self.options.file_options())?,
+            )
+            .with_context(|| format!("open sstable {:?}", path))?;
+            tables.push(Arc::new(table));
+            let table = &tables[tables.len() - 1];
+            if table.first_key().key_ref() != output.first_key
+                || table.last_key().key_ref() != output.last_key
+                || table.table_size() != output.table_size
+            {
+                bail!(
+                    "sstable {:?} does not match the output of compaction job",
+                    path
+                );
+            }
+            Ok(())
+        };
+        let mut result = Ok(());
+        for output in &outputs {
+            result = install(output);
+            if result.is_err() {
+                break;
+            }
+        }
+        if let Err(err) = result {
+            drop(tables);
+            for path in outputs.iter().map(|output| &output.path).chain(&renamed) {
+                let _ = self.fs().remove(path);
+            }
+            return Err(err);
+        }
+        Ok(tables)
+    }
+
+    // pick at most `num_subcompactions - 1` user keys splitting the input into key
+    // ranges of similar size. candidates are the first keys of the sstables and of
+    // their blocks, so each range covers about the same number of blocks.
+    fn subcompaction_boundaries(
+        tables: &[Arc<SsTable>],
+        num_subcompactions: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut keys: Vec<&[u8]> = tables
+            .iter()
+            .flat_map(|table| {
+                std::iter::once(table.first_key().key_ref()).chain(table.index_samples())
+            })
+            .collect();
+        keys.sort();
+        keys.dedup();
+        // the smallest key would start an empty range
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        keys.remove(0);
+
+        let num = std::cmp::min(num_subcompactions, keys.len() + 1);
+        (1..num)
+            .map(|i| keys[i * keys.len() / num].to_vec())
+            .collect()
+    }
+
+    // merge the keys in [start, end) of the input sstables, None means unbounded
+    fn subcompact(
+        &self,
+        task: &CompactionTask,
+        tables: &[Arc<SsTable>],
+        watermark: Version,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let mut iters = Vec::new();
+        for table in tables {
+            if start.is_some_and(|start| table.last_key().key_ref() < start)
+                || end.is_some_and(|end| table.first_key().key_ref() >= end)
+            {
+                continue;
+            }
+            let mut iter = match start {
+                Some(start) => SsTableIterator::create_and_seek_to_key(
+                    table.clone(),
+                    KeySlice::from_slice(start, VERSION_DEFAULT),
+                )?,
+                None => SsTableIterator::create_and_seek_to_first(table.clone())?,
+            };
+            iter.set_readahead(self.options.compaction_readahead_blocks);
+            iters.push(iter);
+        }
+        let level = task.output_level();
+        // values removed by the compaction filter become tombstones, which are
+        // garbage collected like the deleted keys
+        let iter = CompactionFilterIterator::create(
+            MergeIterator::create(iters),
+            self.options.compaction_filter.clone(),
+            level,
+            watermark,
+        );
+        let mut iter = GcIterator::create(iter, watermark, task.is_output_bottom())?;
+
+        // a level 0 sstable is a sorted run by itself, so level 0 output is not cut
+        let target_file_size = if level == 0 {
+            usize::MAX
+        } else {
+            self.options.target_file_size
+        };
+        let mut outputs = Vec::new();
+        if let Err(err) = write_compaction_outputs(
+            &mut iter,
+            end,
+            target_file_size,
+            || SsTableBuilder::create_with_options(&self.options),
+            |builder| self.build_compaction_output(builder),
+            &mut outputs,
+        ) {
+            for table in outputs {
+                let _ = self.fs().remove(&self.path_of_sst(table.id() as usize));
+            }
+            return Err(err);
+        }
+        Ok(outputs)
+    }
+
+    fn build_compaction_output(&self, builder: SsTableBuilder) -> Result<Arc<SsTable>> {
+        let id = self.next_id();
+        let table = builder.build(
+            id as SsTableId,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(id),
+        )?;
+        Ok(Arc::new(table))
+    }
+
+    // delete the files of the obsolete sstables not referred by any state
+    fn purge_obsolete_sstables(&self) -> Result<()> {
+        let purged: Vec<Arc<SsTableInfo>> = {
+            let mut obsolete = self.obsolete_sstables.lock();
+            let (purged, kept) = obsolete
+                .drain(..)
+                .partition(|info| Arc::strong_count(info) == 1);
+            *obsolete = kept;
+            purged
+        };
+        for info in purged {
+            self.table_cache.evict(info.id());
+            self.fs().remove(&self.path_of_sst(info.id() as usize))?;
+        }
+        Ok(())
     }
 }
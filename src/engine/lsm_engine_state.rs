@@ -12,19 +12,105 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::compact::CompactionTask;
 use crate::memtable::Memtable;
+use crate::table::SsTableId;
+use crate::table::SsTableInfo;
 
+#[derive(Clone)]
 pub struct LsmEngineState {
     // current memtable
     pub memtable: Arc<Memtable>,
+
+    // immutable memtables waiting to be flushed, from the newest to the oldest
+    pub imm_memtables: Vec<Arc<Memtable>>,
+
+    // level 0 sstables, from the newest to the oldest, may overlap with each other
+    pub l0_sstables: Vec<SsTableId>,
+
+    // (level, sstables) of level 1 to level `num_levels`,
+    // sstables in the same level are sorted by key and never overlap
+    pub levels: Vec<(usize, Vec<SsTableId>)>,
+
+    // all the sstables in level 0 and levels, the tables are opened through the table cache
+    pub sstables: HashMap<SsTableId, Arc<SsTableInfo>>,
 }
 
 impl LsmEngineState {
-    pub fn create() -> Self {
+    pub fn create(memtable: Memtable, num_levels: usize) -> Self {
         Self {
-            memtable: Arc::new(Memtable::new(0)),
+            memtable: Arc::new(memtable),
+            imm_memtables: Vec::new(),
+            l0_sstables: Vec::new(),
+            levels: (1..=num_levels).map(|level| (level, Vec::new())).collect(),
+            sstables: HashMap::new(),
+        }
+    }
+
+    // return sstable ids of `level`, level 0 included
+    pub fn level_sstables(&self, level: usize) -> &[SsTableId] {
+        if level == 0 {
+            &self.l0_sstables
+        } else {
+            &self.levels[level - 1].1
+        }
+    }
+
+    // total size in bytes of the sstables in `level`
+    pub fn level_size(&self, level: usize) -> usize {
+        self.level_sstables(level)
+            .iter()
+            .map(|id| self.sstables[id].table_size())
+            .sum()
+    }
+
+    // return true if any sstable in `level` overlaps [first, last]
+    pub fn level_overlaps(&self, level: usize, first: &[u8], last: &[u8]) -> bool {
+        self.level_sstables(level)
+            .iter()
+            .any(|id| self.sstables[id].overlaps_key_range(first, last))
+    }
+
+    // replace the input sstables of `task` with `outputs`, return the removed sstables
+    pub fn apply_compaction(
+        &mut self,
+        task: &CompactionTask,
+        outputs: Vec<Arc<SsTableInfo>>,
+    ) -> Vec<Arc<SsTableInfo>> {
+        let inputs = task.input_sstables();
+        // level 0 outputs take the place of the inputs, they are older than the
+        // sstables before the inputs and newer than the ones after
+        let mut l0_pos = self
+            .l0_sstables
+            .iter()
+            .position(|id| inputs.contains(id))
+            .unwrap_or(self.l0_sstables.len());
+        self.l0_sstables.retain(|id| !inputs.contains(id));
+        for (_, ids) in &mut self.levels {
+            ids.retain(|id| !inputs.contains(id));
+        }
+        let removed = inputs
+            .iter()
+            .filter_map(|id| self.sstables.remove(id))
+            .collect();
+
+        let level = task.output_level();
+        for info in outputs {
+            if level == 0 {
+                self.l0_sstables.insert(l0_pos, info.id());
+                l0_pos += 1;
+            } else {
+                let ids = &mut self.levels[level - 1].1;
+                let idx = ids.partition_point(|id| {
+                    self.sstables[id].first_key().key_ref() < info.first_key().key_ref()
+                });
+                ids.insert(idx, info.id());
+            }
+            self.sstables.insert(info.id(), info);
         }
+        removed
     }
 }
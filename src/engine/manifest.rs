@@ -12,115 +12,537 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::Read;
-use std::io::Write;
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::Result;
+use anyhow::bail;
 use bytes::Buf;
 use bytes::BufMut;
 use parking_lot::Mutex;
 use serde::Deserialize;
 use serde::Serialize;
 
-use super::LsmEngineState;
+use crate::base::Error;
+use crate::base::Version;
 use crate::compact::CompactionTask;
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::WritableFile;
+use crate::table::SsTableId;
 
 const MANIFEST: &str = "MANIFEST";
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+// json len + crc32 of json len
+const HEADER_SIZE: usize = 2 * SIZEOF_U32;
 
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    file: Arc<Mutex<Box<dyn WritableFile>>>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    // external sstables ingested in one batch
+    Ingestion(Vec<IngestedFile>),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct IngestedFile {
+    pub id: usize,
+    pub level: usize,
+    pub global_version: Version,
+}
+
+// What is read back from the manifest when it is opened
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct ManifestRecovery {
+    pub records: Vec<ManifestRecord>,
+    // bytes dropped from the tail of the manifest, see `Manifest::recover`
+    pub truncated_bytes: usize,
 }
 
 impl Manifest {
-    pub fn open(path: impl AsRef<Path>, state: &LsmEngineState) -> Result<Self> {
-        let path = path.as_ref();
-        let manifest_path = path.join(MANIFEST);
+    // open the manifest in the engine directory `path`, create it if it does not
+    // exist. return the records in it, see `Manifest::recover` for
+    // `truncate_corrupted`.
+    pub fn open(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        truncate_corrupted: bool,
+    ) -> Result<(Self, ManifestRecovery)> {
+        let manifest_path = path.as_ref().join(MANIFEST);
 
-        if !manifest_path.exists() {
-            let manifest = Manifest::create(path)?;
-            manifest.add_record(ManifestRecord::NewMemtable(state.memtable.id()));
-            Ok(manifest)
+        if !fs.exists(&manifest_path) {
+            Ok((
+                Manifest::create(fs, &manifest_path)?,
+                ManifestRecovery::default(),
+            ))
         } else {
-            let (manifest, records) = Manifest::recover(path)?;
-            Ok(manifest)
+            Manifest::recover(fs, &manifest_path, truncate_corrupted)
         }
     }
 
-    fn create(path: impl AsRef<Path>) -> Result<Self> {
+    fn create(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = fs.create(path, FileOptions::default())?;
+        if let Some(dir) = path.parent() {
+            fs.sync_dir(dir)?;
+        }
         Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)?,
-            )),
+            file: Arc::new(Mutex::new(file)),
         })
     }
 
-    fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
-        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
-
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let mut buf_ptr = buf.as_slice();
+    // Read back the records. A record partially written at the tail is not
+    // acknowledged, so it is dropped. A corrupted record before the tail fails
+    // the recovery with `Error::Corruption`, unless `truncate_corrupted` is set,
+    // then it is dropped with all the records after it. The dropped bytes are
+    // truncated from the file before new records are appended, and their number
+    // is returned.
+    pub(crate) fn recover(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        truncate_corrupted: bool,
+    ) -> Result<(Self, ManifestRecovery)> {
+        let path = path.as_ref();
+        let buf = fs.read_all(path)?;
+        let mut offset = 0;
         let mut records = Vec::new();
-        while buf_ptr.has_remaining() {
-            let (record, buf) = ManifestRecord::decode(buf_ptr)?;
-            records.push(record);
-            buf_ptr = buf;
+        while offset < buf.len() {
+            match ManifestRecord::decode(&buf[offset..]) {
+                Ok(Some((record, len))) => {
+                    records.push(record);
+                    offset += len;
+                }
+                Ok(None) => break,
+                Err(_) if truncate_corrupted => break,
+                Err(err) => {
+                    return Err(err.context(format!("manifest {:?} at offset {}", path, offset)));
+                }
+            }
+        }
+        if offset < buf.len() {
+            Self::rewrite(fs, path, &buf[..offset])?;
         }
 
         Ok((
             Self {
-                file: Arc::new(Mutex::new(file)),
+                file: Arc::new(Mutex::new(fs.append(path)?)),
+            },
+            ManifestRecovery {
+                records,
+                truncated_bytes: buf.len() - offset,
             },
-            records,
         ))
     }
 
+    // replace the content of the manifest atomically
+    fn rewrite(fs: &dyn FileSystem, path: &Path, data: &[u8]) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs.create(&tmp_path, FileOptions::default())?;
+        file.append(data)?;
+        file.sync()?;
+        drop(file);
+        fs.rename(&tmp_path, path)?;
+        if let Some(dir) = path.parent() {
+            fs.sync_dir(dir)?;
+        }
+        Ok(())
+    }
+
     pub fn add_record(&self, record: ManifestRecord) -> Result<()> {
         let mut file = self.file.lock();
-        let (buf_size, buf) = record.encode()?;
+        // a record is written with one append, so that it is never
+        // interleaved with a failed partial write of the header
+        file.append(&record.encode()?)?;
+        file.sync()?;
+        Ok(())
+    }
+}
+
+// Live memtables and sstables folded from the manifest records
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct ManifestState {
+    // memtables not flushed yet, from the oldest to the newest
+    pub memtables: Vec<usize>,
+    // level 0 sstables, from the newest to the oldest
+    pub l0_sstables: Vec<SsTableId>,
+    // sstables of level 1 to `num_levels`, not sorted by key
+    pub levels: Vec<Vec<SsTableId>>,
+    // global versions of the ingested sstables
+    pub global_versions: HashMap<SsTableId, Version>,
+    // max id of the memtables and sstables in the records
+    pub max_id: Option<usize>,
+}
+
+impl ManifestState {
+    pub fn fold(records: &[ManifestRecord], num_levels: usize) -> Result<Self> {
+        let mut state = Self {
+            levels: vec![Vec::new(); num_levels],
+            ..Self::default()
+        };
+        for record in records {
+            state.apply(record)?;
+        }
+        Ok(state)
+    }
 
-        file.write_all(&(buf_size as u64).to_be_bytes())?;
-        file.write_all(&buf)?;
-        file.sync_all();
+    fn apply(&mut self, record: &ManifestRecord) -> Result<()> {
+        match record {
+            ManifestRecord::NewMemtable(id) => {
+                self.memtables.push(*id);
+                self.update_max_id(*id);
+            }
+            ManifestRecord::Flush(id) => {
+                let Some(pos) = self.memtables.iter().position(|memtable| memtable == id) else {
+                    bail!("manifest flushes memtable {} which is not live", id);
+                };
+                self.memtables.remove(pos);
+                self.l0_sstables.insert(0, *id as SsTableId);
+            }
+            ManifestRecord::Compaction(task, outputs) => {
+                let inputs = task.input_sstables();
+                // the same as `LsmEngineState::apply_compaction`, level 0 outputs take
+                // the place of the inputs. the outputs of a trivial move are the inputs.
+                let mut l0_pos = self
+                    .l0_sstables
+                    .iter()
+                    .position(|id| inputs.contains(id))
+                    .unwrap_or(self.l0_sstables.len());
+                self.l0_sstables.retain(|id| !inputs.contains(id));
+                for ids in &mut self.levels {
+                    ids.retain(|id| !inputs.contains(id));
+                }
+                // a trivial move keeps the global versions of the moved sstables
+                for id in &inputs {
+                    if !outputs.iter().any(|output| *output as SsTableId == *id) {
+                        self.global_versions.remove(id);
+                    }
+                }
+
+                let level = task.output_level();
+                for id in outputs {
+                    if level == 0 {
+                        self.l0_sstables.insert(l0_pos, *id as SsTableId);
+                        l0_pos += 1;
+                    } else {
+                        self.level_mut(level)?.push(*id as SsTableId);
+                    }
+                    self.update_max_id(*id);
+                }
+            }
+            ManifestRecord::Ingestion(files) => {
+                for file in files {
+                    let id = file.id as SsTableId;
+                    if file.level == 0 {
+                        self.l0_sstables.insert(0, id);
+                    } else {
+                        self.level_mut(file.level)?.push(id);
+                    }
+                    self.global_versions.insert(id, file.global_version);
+                    self.update_max_id(file.id);
+                }
+            }
+        }
         Ok(())
     }
+
+    fn level_mut(&mut self, level: usize) -> Result<&mut Vec<SsTableId>> {
+        let num_levels = self.levels.len();
+        match self.levels.get_mut(level - 1) {
+            Some(ids) => Ok(ids),
+            None => bail!(
+                "manifest has sstables in level {}, more than {} levels",
+                level,
+                num_levels
+            ),
+        }
+    }
+
+    fn update_max_id(&mut self, id: usize) {
+        self.max_id = Some(self.max_id.map_or(id, |max_id| max_id.max(id)));
+    }
+
+    // all the live sstables
+    pub fn sstables(&self) -> impl Iterator<Item = SsTableId> + '_ {
+        self.l0_sstables
+            .iter()
+            .chain(self.levels.iter().flatten())
+            .copied()
+    }
 }
 
 impl ManifestRecord {
     // manifest record format:
-    // buf len[u64] + json(record) + crc32 of json(record)
-    pub(crate) fn encode(&self) -> Result<(usize, Vec<u8>)> {
-        let mut buf = serde_json::to_vec(self)?;
-        let hash = crc32fast::hash(&buf);
-        let buf_size = buf.len();
-        buf.put_u32(hash);
+    // json len[u32] + crc32 of json len[u32] + json(record) + crc32 of json(record)
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(self)?;
+        let mut buf = Vec::with_capacity(HEADER_SIZE + json.len() + SIZEOF_U32);
+        buf.put_u32(json.len() as u32);
+        buf.put_u32(crc32fast::hash(&buf));
+        buf.put_slice(&json);
+        buf.put_u32(crc32fast::hash(&json));
+        Ok(buf)
+    }
 
-        Ok((buf_size, buf))
+    // decode the first record in `buf`, return it with its encoded length.
+    // return None if `buf` ends in the middle of the record, or the record
+    // reaching the end of `buf` fails the checksum, which is a partial write
+    // as in the wal.
+    pub(crate) fn decode(mut buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        if buf.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let header = &buf[..SIZEOF_U32];
+        let json_len = buf.get_u32() as usize;
+        if buf.get_u32() != crc32fast::hash(header) {
+            return Err(Error::corruption_error(
+                "manifest record header checksum mismatch",
+            ));
+        }
+        if buf.len() < json_len + SIZEOF_U32 {
+            return Ok(None);
+        }
+        let json = &buf[..json_len];
+        buf.advance(json_len);
+        if buf.get_u32() != crc32fast::hash(json) {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return Err(Error::corruption_error("manifest record checksum mismatch"));
+        }
+        let record = serde_json::from_slice(json)
+            .map_err(|err| Error::corruption_error(&format!("bad manifest record: {}", err)))?;
+        Ok(Some((record, HEADER_SIZE + json_len + SIZEOF_U32)))
     }
+}
 
-    pub(crate) fn decode(mut buf: &[u8]) -> Result<(Self, &[u8])> {
-        let buf_size = buf.get_u64();
-        let slice = &buf[..buf_size as usize];
-        let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-        buf.advance(buf_size as usize);
-        let checksum = buf.get_u32();
-        if checksum != crc32fast::hash(slice) {}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
 
-        Ok((json, buf))
+    use anyhow::Result;
+    use bytes::BufMut;
+    use bytes::Bytes;
+
+    use super::HEADER_SIZE;
+    use super::MANIFEST;
+    use super::Manifest;
+    use super::ManifestRecord;
+    use super::ManifestRecovery;
+    use super::ManifestState;
+    use crate::base::Error;
+    use crate::compact::CompactionTask;
+    use crate::compact::FifoCompactionTask;
+    use crate::compact::LeveledCompactionTask;
+    use crate::engine::IngestedFile;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+    use crate::fs::MemFileSystem;
+    use crate::fs::run_crash_test;
+
+    #[test]
+    fn test_manifest_crash() -> Result<()> {
+        let dir = Path::new("/db");
+        let path = dir.join(MANIFEST);
+        run_crash_test(
+            |fs, acknowledged| {
+                fs.create_dir_all(dir)?;
+                let (manifest, recovery) = Manifest::open(fs.as_ref(), dir, false)?;
+                assert!(recovery.records.is_empty());
+                manifest.add_record(ManifestRecord::NewMemtable(0))?;
+                acknowledged.push(ManifestRecord::NewMemtable(0));
+                for i in 1..10 {
+                    manifest.add_record(ManifestRecord::NewMemtable(i))?;
+                    acknowledged.push(ManifestRecord::NewMemtable(i));
+                    manifest.add_record(ManifestRecord::Flush(i - 1))?;
+                    acknowledged.push(ManifestRecord::Flush(i - 1));
+                }
+                Ok(())
+            },
+            |fs, acknowledged: &[ManifestRecord]| {
+                if !fs.exists(&path) {
+                    assert!(acknowledged.is_empty());
+                    return Ok(());
+                }
+                // only a record failed in sync may be written in addition
+                let (_, ManifestRecovery { records, .. }) =
+                    Manifest::recover(fs.as_ref(), &path, false)?;
+                assert_eq!(&records[..acknowledged.len()], acknowledged);
+                assert!(records.len() - acknowledged.len() <= 1);
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_fold_manifest_records() -> Result<()> {
+        let leveled = |upper_level, upper, lower_level, lower: Vec<u64>| {
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                upper_level_sstables: upper,
+                lower_level,
+                lower_level_sstables: lower,
+                is_lower_level_bottom: lower_level == 2,
+            })
+        };
+        let records = vec![
+            ManifestRecord::NewMemtable(0),
+            ManifestRecord::NewMemtable(1),
+            ManifestRecord::Flush(0),
+            ManifestRecord::NewMemtable(2),
+            ManifestRecord::Flush(1),
+            ManifestRecord::NewMemtable(3),
+            // level 0 is compacted into level 1
+            ManifestRecord::Compaction(leveled(0, vec![1, 0], 1, vec![]), vec![4, 5]),
+            // a trivial move keeps the ids
+            ManifestRecord::Compaction(leveled(1, vec![4], 2, vec![]), vec![4]),
+            ManifestRecord::Ingestion(vec![
+                IngestedFile {
+                    id: 6,
+                    level: 0,
+                    global_version: 10,
+                },
+                IngestedFile {
+                    id: 7,
+                    level: 2,
+                    global_version: 10,
+                },
+            ]),
+            ManifestRecord::Flush(2),
+            ManifestRecord::NewMemtable(8),
+            ManifestRecord::Compaction(
+                CompactionTask::Fifo(FifoCompactionTask { sstables: vec![6] }),
+                vec![],
+            ),
+            ManifestRecord::Ingestion(vec![IngestedFile {
+                id: 9,
+                level: 1,
+                global_version: 20,
+            }]),
+            // a trivial move of an ingested sstable keeps its global version
+            ManifestRecord::Compaction(leveled(1, vec![9], 2, vec![]), vec![9]),
+        ];
+        let state = ManifestState::fold(&records, 2)?;
+        assert_eq!(state.memtables, vec![3, 8]);
+        assert_eq!(state.l0_sstables, vec![2]);
+        assert_eq!(state.levels, vec![vec![5], vec![4, 7, 9]]);
+        assert_eq!(state.global_versions.len(), 2);
+        assert_eq!(state.global_versions[&7], 10);
+        assert_eq!(state.global_versions[&9], 20);
+        assert_eq!(state.max_id, Some(9));
+
+        // records not matching the engine are rejected
+        assert!(ManifestState::fold(&records, 1).is_err());
+        assert!(ManifestState::fold(&[ManifestRecord::Flush(0)], 2).is_err());
+        assert_eq!(ManifestState::fold(&[], 2)?.max_id, None);
+
+        Ok(())
+    }
+
+    // write `records` into a new manifest and return its content
+    fn write_manifest(
+        fs: &dyn FileSystem,
+        dir: &Path,
+        records: &[ManifestRecord],
+    ) -> Result<Bytes> {
+        fs.create_dir_all(dir)?;
+        let (manifest, _) = Manifest::open(fs, dir, false)?;
+        for record in records {
+            manifest.add_record(record.clone())?;
+        }
+        fs.read_all(&dir.join(MANIFEST))
+    }
+
+    fn overwrite(fs: &dyn FileSystem, path: &Path, data: &[u8]) -> Result<()> {
+        let mut file = fs.create(path, FileOptions::default())?;
+        file.append(data)?;
+        file.sync()
+    }
+
+    #[test]
+    fn test_manifest_truncated_tail() -> Result<()> {
+        let fs = MemFileSystem::new();
+        let dir = Path::new("/db");
+        let path = dir.join(MANIFEST);
+        let records: Vec<_> = (0..3).map(ManifestRecord::NewMemtable).collect();
+        let data = write_manifest(&fs, dir, &records)?;
+        let last_len = ManifestRecord::NewMemtable(2).encode()?.len();
+
+        // the last record cut at any point is dropped, as well as a last record
+        // with a broken body
+        let good_len = data.len() - last_len;
+        let mut broken = data.to_vec();
+        *broken.last_mut().unwrap() ^= 0xff;
+        let tails = (good_len..data.len())
+            .map(|len| data[..len].to_vec())
+            .chain([broken]);
+        for tail in tails {
+            overwrite(&fs, &path, &tail)?;
+            let (manifest, recovery) = Manifest::open(&fs, dir, false)?;
+            assert_eq!(recovery.records, records[..2]);
+            assert_eq!(recovery.truncated_bytes, tail.len() - good_len);
+            // the tail is truncated before appending
+            assert_eq!(fs.read_all(&path)?.len(), good_len);
+            manifest.add_record(ManifestRecord::Flush(0))?;
+            drop(manifest);
+            let (_, recovery) = Manifest::open(&fs, dir, false)?;
+            assert_eq!(recovery.records[..2], records[..2]);
+            assert_eq!(recovery.records[2], ManifestRecord::Flush(0));
+            assert_eq!(recovery.truncated_bytes, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_corruption() -> Result<()> {
+        let fs = MemFileSystem::new();
+        let dir = Path::new("/db");
+        let path = dir.join(MANIFEST);
+        let records: Vec<_> = (0..3).map(ManifestRecord::NewMemtable).collect();
+        let data = write_manifest(&fs, dir, &records)?;
+        let record_len = ManifestRecord::NewMemtable(0).encode()?.len();
+
+        // break the header and the body of the second record
+        for offset in [record_len, record_len + HEADER_SIZE + 2] {
+            let mut corrupted = data.to_vec();
+            corrupted[offset] ^= 0xff;
+            overwrite(&fs, &path, &corrupted)?;
+            let err = Manifest::open(&fs, dir, false).err().unwrap();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::Corruption(_))
+            ));
+            // the file is untouched
+            assert_eq!(fs.read_all(&path)?.as_ref(), &corrupted[..]);
+
+            // truncated back to the last good record
+            let (_, recovery) = Manifest::open(&fs, dir, true)?;
+            assert_eq!(recovery.records, records[..1]);
+            assert_eq!(recovery.truncated_bytes, data.len() - record_len);
+            assert_eq!(fs.read_all(&path)?.as_ref(), &data[..record_len]);
+            assert!(!fs.exists(&path.with_extension("tmp")));
+        }
+
+        // a record passing the checksum but not parsed is also corrupted
+        let json = b"{}";
+        let mut bad = Vec::new();
+        bad.put_u32(json.len() as u32);
+        bad.put_u32(crc32fast::hash(&bad));
+        bad.put_slice(json);
+        bad.put_u32(crc32fast::hash(json));
+        overwrite(&fs, &path, &bad)?;
+        let err = Manifest::open(&fs, dir, false).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Corruption(_))
+        ));
+
+        Ok(())
     }
 }
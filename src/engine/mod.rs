@@ -22,4 +22,9 @@ pub use lsm_engine::LsmEngine;
 pub use lsm_engine::WriteBatchRecord;
 pub use lsm_engine_inner::LsmEngineInner;
 pub use lsm_engine_state::LsmEngineState;
+pub use manifest::IngestedFile;
+pub use manifest::Manifest;
+pub use manifest::ManifestRecord;
+pub use manifest::ManifestRecovery;
+pub use manifest::ManifestState;
 pub use options::LsmOptions;
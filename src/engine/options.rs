@@ -12,10 +12,147 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::compact::CompactionFilter;
+use crate::compact::CompactionOptions;
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::PosixFileSystem;
+use crate::fs::RateLimiter;
+use crate::table::TablePropertiesCollectorFactory;
+
 pub struct LsmOptions {
+    // All the files of engine are accessed through it
+    pub fs: Arc<dyn FileSystem>,
+
     // Block size in bytes
     pub block_size: usize,
 
     // number of block cache
     pub block_cache_num: usize,
+
+    // Max number of sstables kept opened, each of them holds a file descriptor
+    // and the parsed index and filter. 0 means no limit.
+    pub max_open_files: usize,
+
+    // Size in bytes of an index partition of sstables, 0 means
+    // the index is not partitioned and loaded in memory as a whole
+    pub index_partition_size: usize,
+
+    // Every sstable built by the engine gets a collector from each factory
+    pub table_properties_collector_factories: Vec<Arc<dyn TablePropertiesCollectorFactory>>,
+
+    // Strategy of picking compaction tasks, which run in a background thread
+    pub compaction_options: CompactionOptions,
+
+    // Called for the key-value pairs visited by compaction, see `CompactionFilter`
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    // Output sstables of compaction are cut at this size in bytes, versions
+    // of the same key are never cut into different sstables
+    pub target_file_size: usize,
+
+    // Path of the `compaction_worker` binary. If set, compactions run in a worker
+    // process, which reads the input sstables and writes the outputs on local disk.
+    // It can not be used with a compaction filter.
+    pub compaction_worker: Option<PathBuf>,
+
+    // Number of blocks read at once by the input iterators of compaction
+    pub compaction_readahead_blocks: usize,
+
+    // A compaction is split into at most this many key ranges compacted in
+    // parallel threads, 1 means compactions are not split
+    pub max_subcompactions: usize,
+
+    // Compact the sstables searched too often by lookups without finding the keys,
+    // see `SsTableInfo::allowed_seeks`. Only leveled compaction picks them.
+    pub seek_compaction: bool,
+
+    // Memtable is frozen and flushed into level 0 when its size exceeds this
+    pub memtable_size: usize,
+
+    // Number of levels below level 0
+    pub num_levels: usize,
+
+    // Shared by the writes of flush, compaction and ingestion, flush has
+    // the high priority. None means the writes are not throttled.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+
+    // If a manifest record before the tail is corrupted, drop it and the records
+    // after it instead of failing to open. The changes in the dropped records are
+    // lost, and their sstables are removed.
+    pub truncate_corrupted_manifest: bool,
+
+    // Save writes into wal before inserting into memtable
+    pub enable_wal: bool,
+
+    // Sync the wal before a write returns, so that acknowledged writes survive
+    // a machine crash. If disabled, the writes only reach the OS when the write
+    // returns, and are synced when the memtable is frozen.
+    pub sync_wal: bool,
+
+    // Read sstables through memory mapping, blocks are read without copying.
+    // Useful when the data fits in page cache.
+    pub use_mmap_reads: bool,
+
+    // Read sstables with direct I/O, bypassing the page cache.
+    // Can not be enabled together with `use_mmap_reads`.
+    pub use_direct_reads: bool,
+
+    // Write sstables of flush and compaction with direct I/O, so that the
+    // output does not evict the hot data in page cache.
+    pub use_direct_io_for_flush_and_compaction: bool,
+}
+
+impl Default for LsmOptions {
+    fn default() -> Self {
+        Self {
+            fs: Arc::new(PosixFileSystem),
+            block_size: 4096,
+            block_cache_num: 1024,
+            max_open_files: 0,
+            index_partition_size: 0,
+            table_properties_collector_factories: Vec::new(),
+            compaction_options: CompactionOptions::default(),
+            compaction_filter: None,
+            target_file_size: 64 << 20,
+            compaction_worker: None,
+            compaction_readahead_blocks: 8,
+            max_subcompactions: 1,
+            seek_compaction: false,
+            memtable_size: 4 << 20,
+            num_levels: 6,
+            rate_limiter: None,
+            truncate_corrupted_manifest: false,
+            enable_wal: true,
+            sync_wal: true,
+            use_mmap_reads: false,
+            use_direct_reads: false,
+            use_direct_io_for_flush_and_compaction: false,
+        }
+    }
+}
+
+impl LsmOptions {
+    // options of sstable files opened or built by the engine
+    pub fn file_options(&self) -> FileOptions {
+        FileOptions {
+            use_mmap: self.use_mmap_reads,
+            use_direct_reads: self.use_direct_reads,
+            use_direct_writes: self.use_direct_io_for_flush_and_compaction,
+        }
+    }
+
+    // options of sstable files read as compaction inputs, they are read with direct
+    // I/O if `use_direct_io_for_flush_and_compaction` is enabled
+    pub fn compaction_input_file_options(&self) -> FileOptions {
+        let mut options = self.file_options();
+        if self.use_direct_io_for_flush_and_compaction {
+            options.use_mmap = false;
+            options.use_direct_reads = true;
+        }
+        options
+    }
 }
@@ -0,0 +1,104 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::alloc::Layout;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::ptr::NonNull;
+
+// Alignment of the memory address, file offset and length of direct I/O
+pub(crate) const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+pub(crate) fn align_down(value: usize, alignment: usize) -> usize {
+    value / alignment * alignment
+}
+
+pub(crate) fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+// A zero initialized buffer with aligned address and length, used by direct I/O
+pub(crate) struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+}
+
+// Safety: AlignedBuf owns the memory exclusively like a `Vec<u8>`
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    // create a buffer with `len` rounded up to `DIRECT_IO_ALIGNMENT`
+    pub fn new(len: usize) -> Self {
+        let len = align_up(len, DIRECT_IO_ALIGNMENT);
+        // zero sized allocation is not allowed
+        let layout =
+            Layout::from_size_align(len.max(DIRECT_IO_ALIGNMENT), DIRECT_IO_ALIGNMENT).unwrap();
+        // Safety: the size of layout is not zero
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout);
+        };
+        Self { ptr, len, layout }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: ptr is valid for `len` initialized bytes
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // Safety: ptr is valid for `len` initialized bytes and exclusively borrowed
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // Safety: ptr is allocated with the same layout
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AlignedBuf;
+    use super::DIRECT_IO_ALIGNMENT;
+    use super::align_down;
+    use super::align_up;
+
+    #[test]
+    fn test_aligned_buf() {
+        assert_eq!(align_down(4097, 4096), 4096);
+        assert_eq!(align_up(4097, 4096), 8192);
+        assert_eq!(align_up(4096, 4096), 4096);
+        assert_eq!(align_up(0, 4096), 0);
+
+        let mut buf = AlignedBuf::new(100);
+        assert_eq!(buf.len(), DIRECT_IO_ALIGNMENT);
+        assert_eq!(buf.as_ptr() as usize % DIRECT_IO_ALIGNMENT, 0);
+        assert!(buf.iter().all(|b| *b == 0));
+        buf[99] = 1;
+        assert_eq!(buf[99], 1);
+
+        assert!(AlignedBuf::new(0).is_empty());
+    }
+}
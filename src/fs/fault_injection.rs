@@ -0,0 +1,502 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use anyhow::bail;
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::FileOptions;
+use super::FileSystem;
+use super::RandomAccessFile;
+use super::WritableFile;
+
+// How reads of the file system are broken
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ReadFault {
+    #[default]
+    None,
+    // return only the first half of the requested bytes
+    ShortRead,
+    // flip the bits of a byte in the middle of the requested bytes
+    Corruption,
+}
+
+// File system wrapper for crash-consistency testing. It remembers how much of
+// each file written through it is synced, and which directory entries are not
+// synced by `sync_dir` yet, `drop_unsynced_data` then makes the underlying files
+// look like the machine lost power. It can also fail writes or syncs after a
+// number of operations, and break the reads.
+pub struct FaultInjectionFileSystem {
+    base: Arc<dyn FileSystem>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Default)]
+struct FaultState {
+    files: HashMap<PathBuf, FileState>,
+    // directory entries changed since the last sync of their directory, in order
+    unsynced_entries: Vec<EntryChange>,
+    // number of write operations allowed before all of them fail
+    writes_before_failure: Option<usize>,
+    // number of syncs allowed before all of them fail
+    syncs_before_failure: Option<usize>,
+    read_fault: ReadFault,
+}
+
+#[derive(Clone, Copy)]
+struct FileState {
+    written: usize,
+    // None if the file is created and not synced yet
+    synced: Option<usize>,
+}
+
+enum EntryChange {
+    Create(PathBuf),
+    // content of `to` before the rename, None if it did not exist
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        replaced: Option<Bytes>,
+    },
+}
+
+impl EntryChange {
+    fn path(&self) -> &Path {
+        match self {
+            EntryChange::Create(path) => path,
+            EntryChange::Rename { to, .. } => to,
+        }
+    }
+}
+
+impl FaultState {
+    fn check_write(&mut self) -> Result<()> {
+        Self::consume(&mut self.writes_before_failure, "write")
+    }
+
+    fn check_sync(&mut self) -> Result<()> {
+        Self::consume(&mut self.syncs_before_failure, "sync")
+    }
+
+    fn consume(remaining: &mut Option<usize>, op: &str) -> Result<()> {
+        match remaining {
+            Some(0) => bail!("injected {} failure", op),
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(base: Arc<dyn FileSystem>) -> Self {
+        Self {
+            base,
+            state: Arc::default(),
+        }
+    }
+
+    // fail all the writes (create, append, rename, remove and hard link)
+    // after `n` of them succeed, None stops failing
+    pub fn fail_writes_after(&self, n: Option<usize>) {
+        self.state.lock().writes_before_failure = n;
+    }
+
+    // fail all the syncs of files and directories after `n` of them succeed,
+    // None stops failing
+    pub fn fail_syncs_after(&self, n: Option<usize>) {
+        self.state.lock().syncs_before_failure = n;
+    }
+
+    pub fn set_read_fault(&self, fault: ReadFault) {
+        self.state.lock().read_fault = fault;
+    }
+
+    // return true if a write or sync has failed because of the injected faults
+    pub fn has_failed(&self) -> bool {
+        let state = self.state.lock();
+        state.writes_before_failure == Some(0) || state.syncs_before_failure == Some(0)
+    }
+
+    // Simulate a power loss: files never synced are removed, the others are
+    // truncated to their synced length. Then the directory entries not synced
+    // are undone from the newest one: created files are removed, and renamed
+    // files are moved back. All the writable files must be dropped before,
+    // and the injected faults are cleared.
+    pub fn drop_unsynced_data(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        for (path, file) in state.files.drain() {
+            match file.synced {
+                None => self.base.remove(&path)?,
+                Some(synced) if synced < file.written => {
+                    let data = self.base.read_all(&path)?;
+                    self.overwrite(&path, &data[..synced])?;
+                }
+                Some(_) => {}
+            }
+        }
+        for change in state.unsynced_entries.drain(..).rev() {
+            match change {
+                EntryChange::Create(path) => {
+                    if self.base.exists(&path) {
+                        self.base.remove(&path)?;
+                    }
+                }
+                EntryChange::Rename { from, to, replaced } => {
+                    if self.base.exists(&to) {
+                        let data = self.base.read_all(&to)?;
+                        self.overwrite(&from, &data)?;
+                    }
+                    match replaced {
+                        Some(data) => self.overwrite(&to, &data)?,
+                        None if self.base.exists(&to) => self.base.remove(&to)?,
+                        None => {}
+                    }
+                }
+            }
+        }
+        state.writes_before_failure = None;
+        state.syncs_before_failure = None;
+        state.read_fault = ReadFault::None;
+        Ok(())
+    }
+
+    fn overwrite(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut writer = self.base.create(path, FileOptions::default())?;
+        writer.append(data)?;
+        writer.sync()
+    }
+}
+
+impl FileSystem for FaultInjectionFileSystem {
+    fn create(&self, path: &Path, options: FileOptions) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        let exists = self.base.exists(path);
+        let file = self.base.create(path, options)?;
+        if !exists {
+            state
+                .unsynced_entries
+                .push(EntryChange::Create(path.to_path_buf()));
+        }
+        state.files.insert(path.to_path_buf(), FileState {
+            written: 0,
+            synced: None,
+        });
+        Ok(Box::new(FaultWritableFile {
+            file,
+            path: path.to_path_buf(),
+            state: self.state.clone(),
+        }))
+    }
+
+    fn append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        let file = self.base.append(path)?;
+        if !state.files.contains_key(path) {
+            // existing content not written through us is taken as synced
+            let size = self.base.open(path, FileOptions::default())?.size();
+            state.files.insert(path.to_path_buf(), FileState {
+                written: size,
+                synced: Some(size),
+            });
+        }
+        Ok(Box::new(FaultWritableFile {
+            file,
+            path: path.to_path_buf(),
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open(&self, path: &Path, options: FileOptions) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(FaultRandomAccessFile {
+            file: self.base.open(path, options)?,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        let replaced = if self.base.exists(to) {
+            Some(self.base.read_all(to)?)
+        } else {
+            None
+        };
+        self.base.rename(from, to)?;
+        if let Some(file) = state.files.remove(from) {
+            state.files.insert(to.to_path_buf(), file);
+        }
+        state.unsynced_entries.push(EntryChange::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            replaced,
+        });
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.base.remove(path)?;
+        state.files.remove(path);
+        // removing is taken as durable, the entry changes of the file are
+        // not undone any more
+        state
+            .unsynced_entries
+            .retain(|change| change.path() != path);
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.base.hard_link(from, to)?;
+        state
+            .unsynced_entries
+            .push(EntryChange::Create(to.to_path_buf()));
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.base.list(dir)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.base.exists(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.state.lock().check_write()?;
+        self.base.create_dir_all(path)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_sync()?;
+        self.base.sync_dir(dir)?;
+        state
+            .unsynced_entries
+            .retain(|change| change.path().parent() != Some(dir));
+        Ok(())
+    }
+}
+
+struct FaultWritableFile {
+    file: Box<dyn WritableFile>,
+    path: PathBuf,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl WritableFile for FaultWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_write()?;
+        self.file.append(data)?;
+        if let Some(file) = state.files.get_mut(&self.path) {
+            file.written += data.len();
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_sync()?;
+        self.file.sync()?;
+        if let Some(file) = state.files.get_mut(&self.path) {
+            file.synced = Some(file.written);
+        }
+        Ok(())
+    }
+}
+
+struct FaultRandomAccessFile {
+    file: Box<dyn RandomAccessFile>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultRandomAccessFile {
+    fn inject(&self, data: Bytes) -> Bytes {
+        match self.state.lock().read_fault {
+            ReadFault::None => data,
+            ReadFault::ShortRead => data.slice(..data.len() / 2),
+            ReadFault::Corruption if !data.is_empty() => {
+                let mut data = data.to_vec();
+                let mid = data.len() / 2;
+                data[mid] ^= 0xff;
+                data.into()
+            }
+            ReadFault::Corruption => data,
+        }
+    }
+}
+
+impl RandomAccessFile for FaultRandomAccessFile {
+    fn read(&self, offset: u64, len: u64) -> Result<Bytes> {
+        Ok(self.inject(self.file.read(offset, len)?))
+    }
+
+    fn read_batch(&self, ranges: &[(u64, u64)]) -> Result<Vec<Bytes>> {
+        Ok(self
+            .file
+            .read_batch(ranges)?
+            .into_iter()
+            .map(|data| self.inject(data))
+            .collect())
+    }
+
+    fn size(&self) -> usize {
+        self.file.size()
+    }
+}
+
+// Crash test harness. `workload` runs against a fresh file system again and
+// again, the n-th run fails all the writes (then in another round, all the
+// syncs) after n of them succeed, as if the process was killed there. The
+// workload pushes what is acknowledged into the vector. After the run the
+// unsynced data is dropped and `check` verifies the acknowledged items survive.
+// Stops when a run completes without hitting the fault.
+#[cfg(test)]
+pub(crate) fn run_crash_test<T>(
+    mut workload: impl FnMut(&Arc<FaultInjectionFileSystem>, &mut Vec<T>) -> Result<()>,
+    mut check: impl FnMut(&Arc<FaultInjectionFileSystem>, &[T]) -> Result<()>,
+) -> Result<()> {
+    use super::MemFileSystem;
+
+    for fail_syncs in [false, true] {
+        for n in 0.. {
+            let fs = Arc::new(FaultInjectionFileSystem::new(
+                Arc::new(MemFileSystem::new()),
+            ));
+            if fail_syncs {
+                fs.fail_syncs_after(Some(n));
+            } else {
+                fs.fail_writes_after(Some(n));
+            }
+            let mut acknowledged = Vec::new();
+            let result = workload(&fs, &mut acknowledged);
+            let crashed = fs.has_failed();
+            assert!(result.is_ok() || crashed, "{:?}", result);
+
+            fs.drop_unsynced_data()?;
+            check(&fs, &acknowledged)?;
+            if !crashed {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::FaultInjectionFileSystem;
+    use super::ReadFault;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+    use crate::fs::MemFileSystem;
+
+    #[test]
+    fn test_fault_injection() -> Result<()> {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        let dir = Path::new("/db");
+        fs.create_dir_all(dir)?;
+
+        // synced data survives the crash, the unsynced tail and files are dropped
+        let mut file = fs.create(&dir.join("a"), FileOptions::default())?;
+        file.append(b"hello")?;
+        file.sync()?;
+        file.append(b" world")?;
+        let mut file = fs.create(&dir.join("b"), FileOptions::default())?;
+        file.append(b"lost")?;
+        drop(file);
+        fs.sync_dir(dir)?;
+        fs.drop_unsynced_data()?;
+        assert_eq!(fs.read_all(&dir.join("a"))?.as_ref(), b"hello");
+        assert!(!fs.exists(&dir.join("b")));
+
+        // synced files are lost if the directory is not synced after creating them
+        let mut file = fs.create(&dir.join("b"), FileOptions::default())?;
+        file.append(b"lost")?;
+        file.sync()?;
+        drop(file);
+        fs.hard_link(&dir.join("a"), &dir.join("c"))?;
+        fs.drop_unsynced_data()?;
+        assert!(!fs.exists(&dir.join("b")));
+        assert!(!fs.exists(&dir.join("c")));
+        assert_eq!(fs.read_all(&dir.join("a"))?.as_ref(), b"hello");
+
+        // renames not synced by the directory are undone
+        let mut file = fs.create(&dir.join("tmp"), FileOptions::default())?;
+        file.append(b"new")?;
+        file.sync()?;
+        drop(file);
+        fs.sync_dir(dir)?;
+        fs.rename(&dir.join("tmp"), &dir.join("a"))?;
+        fs.drop_unsynced_data()?;
+        assert_eq!(fs.read_all(&dir.join("a"))?.as_ref(), b"hello");
+        assert_eq!(fs.read_all(&dir.join("tmp"))?.as_ref(), b"new");
+        fs.rename(&dir.join("tmp"), &dir.join("b"))?;
+        fs.sync_dir(dir)?;
+        fs.drop_unsynced_data()?;
+        assert!(!fs.exists(&dir.join("tmp")));
+        assert_eq!(fs.read_all(&dir.join("b"))?.as_ref(), b"new");
+        fs.remove(&dir.join("b"))?;
+
+        // content written before is kept when appending
+        let mut file = fs.append(&dir.join("a"))?;
+        file.append(b"!")?;
+        drop(file);
+        fs.drop_unsynced_data()?;
+        assert_eq!(fs.read_all(&dir.join("a"))?.as_ref(), b"hello");
+
+        // writes and syncs fail after the given number of operations
+        fs.fail_writes_after(Some(2));
+        let mut file = fs.create(&dir.join("c"), FileOptions::default())?;
+        file.append(b"1")?;
+        assert!(file.append(b"2").is_err());
+        assert!(fs.remove(&dir.join("a")).is_err());
+        assert!(fs.has_failed());
+        fs.fail_writes_after(None);
+        fs.fail_syncs_after(Some(0));
+        assert!(file.sync().is_err());
+        drop(file);
+        fs.drop_unsynced_data()?;
+        assert!(!fs.exists(&dir.join("c")));
+        assert!(!fs.has_failed());
+
+        // broken reads
+        let file = fs.open(&dir.join("a"), FileOptions::default())?;
+        fs.set_read_fault(ReadFault::ShortRead);
+        assert_eq!(file.read(0, 4)?.as_ref(), b"he");
+        fs.set_read_fault(ReadFault::Corruption);
+        assert_eq!(file.read_batch(&[(0, 5)])?[0].as_ref(), b"he\x93lo");
+        fs.set_read_fault(ReadFault::None);
+        assert_eq!(file.read(0, 5)?.as_ref(), b"hello");
+
+        Ok(())
+    }
+}
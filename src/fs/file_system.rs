@@ -0,0 +1,92 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+// Options of how a file is read and written, only respected by file systems
+// backed by real files
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct FileOptions {
+    // map the whole file into memory, reads return slices of the mapping
+    // without copying. the file must not be truncated while it is mapped.
+    pub use_mmap: bool,
+
+    // read the file with O_DIRECT, bypassing the page cache
+    pub use_direct_reads: bool,
+
+    // write the file with O_DIRECT, bypassing the page cache
+    pub use_direct_writes: bool,
+}
+
+// A file opened for sequential writing, written data is durable only after `sync`
+pub trait WritableFile: Send {
+    fn append(&mut self, data: &[u8]) -> Result<()>;
+
+    fn sync(&mut self) -> Result<()>;
+}
+
+// A file opened for positional reading
+pub trait RandomAccessFile: Send + Sync {
+    fn read(&self, offset: u64, len: u64) -> Result<Bytes>;
+
+    // read the (offset, len) ranges, implementations may submit them together
+    fn read_batch(&self, ranges: &[(u64, u64)]) -> Result<Vec<Bytes>> {
+        ranges
+            .iter()
+            .map(|(offset, len)| self.read(*offset, *len))
+            .collect()
+    }
+
+    fn size(&self) -> usize;
+}
+
+// All the file operations of the engine go through this trait,
+// so that the engine can run on files other than the local disk.
+pub trait FileSystem: Send + Sync {
+    // create a file for writing, truncate it if it exists
+    fn create(&self, path: &Path, options: FileOptions) -> Result<Box<dyn WritableFile>>;
+
+    // open an existing file for appending
+    fn append(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    // open an existing file for reading
+    fn open(&self, path: &Path, options: FileOptions) -> Result<Box<dyn RandomAccessFile>>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    // make `to` refer to the same file as `from`
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()>;
+
+    // return paths of the files and directories in `dir`, sorted by name
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    // make the creation, rename and removal of files in `dir` durable
+    fn sync_dir(&self, dir: &Path) -> Result<()>;
+
+    // read the whole file
+    fn read_all(&self, path: &Path) -> Result<Bytes> {
+        let file = self.open(path, FileOptions::default())?;
+        file.read(0, file.size() as u64)
+    }
+}
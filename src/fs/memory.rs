@@ -0,0 +1,246 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use anyhow::bail;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+
+use super::FileOptions;
+use super::FileSystem;
+use super::RandomAccessFile;
+use super::WritableFile;
+
+// content of a file, shared by its hard links and writers
+type MemFileData = Arc<RwLock<Vec<u8>>>;
+
+// File system in memory, all the files are lost when it is dropped.
+// Useful for tests and ephemeral databases.
+#[derive(Default)]
+pub struct MemFileSystem {
+    inner: Mutex<MemFileSystemInner>,
+}
+
+#[derive(Default)]
+struct MemFileSystemInner {
+    files: HashMap<PathBuf, MemFileData>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl MemFileSystemInner {
+    fn check_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(parent)
+                if !parent.as_os_str().is_empty()
+                    && parent != Path::new("/")
+                    && !self.dirs.contains(parent) =>
+            {
+                bail!("directory {:?} not found", parent)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> Result<MemFileData> {
+        match self.files.get(path) {
+            Some(data) => Ok(data.clone()),
+            None => bail!("file {:?} not found", path),
+        }
+    }
+}
+
+impl MemFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FileSystem for MemFileSystem {
+    fn create(&self, path: &Path, _options: FileOptions) -> Result<Box<dyn WritableFile>> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(path)?;
+        let data = MemFileData::default();
+        inner.files.insert(path.to_path_buf(), data.clone());
+        Ok(Box::new(MemWritableFile { data }))
+    }
+
+    fn append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let data = self.inner.lock().file(path)?;
+        Ok(Box::new(MemWritableFile { data }))
+    }
+
+    fn open(&self, path: &Path, _options: FileOptions) -> Result<Box<dyn RandomAccessFile>> {
+        let data = self.inner.lock().file(path)?;
+        // files are not modified after they are opened for reading, take a snapshot
+        // so that reads return slices without copying
+        let data = Bytes::from(data.read().clone());
+        Ok(Box::new(MemRandomAccessFile { data }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(to)?;
+        let data = inner.file(from)?;
+        inner.files.remove(from);
+        inner.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        if self.inner.lock().files.remove(path).is_none() {
+            bail!("file {:?} not found", path);
+        }
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut inner = self.inner.lock();
+        inner.check_parent(to)?;
+        if inner.files.contains_key(to) {
+            bail!("file {:?} already exists", to);
+        }
+        let data = inner.file(from)?;
+        inner.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let inner = self.inner.lock();
+        if !inner.dirs.contains(dir) {
+            bail!("directory {:?} not found", dir);
+        }
+        let mut paths: Vec<PathBuf> = inner
+            .files
+            .keys()
+            .chain(inner.dirs.iter())
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let inner = self.inner.lock();
+        inner.files.contains_key(path) || inner.dirs.contains(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut inner = self.inner.lock();
+        for dir in path.ancestors() {
+            if inner.files.contains_key(dir) {
+                bail!("{:?} is not a directory", dir);
+            }
+            if !dir.as_os_str().is_empty() {
+                inner.dirs.insert(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        if !self.inner.lock().dirs.contains(dir) {
+            bail!("directory {:?} not found", dir);
+        }
+        Ok(())
+    }
+}
+
+struct MemWritableFile {
+    data: MemFileData,
+}
+
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.data.write().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct MemRandomAccessFile {
+    data: Bytes,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read(&self, offset: u64, len: u64) -> Result<Bytes> {
+        let (offset, len) = (offset as usize, len as usize);
+        if offset + len > self.data.len() {
+            bail!("read [{}, {}) reaches end of file", offset, offset + len);
+        }
+        Ok(self.data.slice(offset..offset + len))
+    }
+
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    use super::MemFileSystem;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+
+    #[test]
+    fn test_mem_file_system() -> Result<()> {
+        let fs = MemFileSystem::new();
+        let dir = Path::new("/db/a");
+        let path = dir.join("1");
+        assert!(fs.create(&path, FileOptions::default()).is_err());
+        fs.create_dir_all(dir)?;
+        assert!(fs.exists(Path::new("/db")));
+
+        let mut file = fs.create(&path, FileOptions::default())?;
+        file.append(b"hello")?;
+        file.sync()?;
+        let mut file = fs.append(&path)?;
+        file.append(b" world")?;
+        let reader = fs.open(&path, FileOptions::default())?;
+        assert_eq!(reader.size(), 11);
+        assert_eq!(reader.read(6, 5)?.as_ref(), b"world");
+        assert!(reader.read(6, 6).is_err());
+
+        fs.hard_link(&path, &dir.join("2"))?;
+        fs.rename(&path, &dir.join("3"))?;
+        fs.create_dir_all(&dir.join("sub"))?;
+        assert_eq!(fs.list(dir)?, vec![
+            dir.join("2"),
+            dir.join("3"),
+            dir.join("sub")
+        ]);
+        fs.remove(&dir.join("3"))?;
+        assert!(!fs.exists(&dir.join("3")));
+        assert!(fs.remove(&dir.join("3")).is_err());
+        // linked files share the data
+        file.append(b"!")?;
+        assert_eq!(fs.read_all(&dir.join("2"))?.as_ref(), b"hello world!");
+        fs.sync_dir(dir)?;
+        Ok(())
+    }
+}
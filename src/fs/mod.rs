@@ -0,0 +1,35 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod aligned_buf;
+mod fault_injection;
+mod file_system;
+mod memory;
+mod posix;
+mod rate_limiter;
+#[cfg(feature = "io-uring")]
+mod uring;
+
+pub use fault_injection::FaultInjectionFileSystem;
+pub use fault_injection::ReadFault;
+#[cfg(test)]
+pub(crate) use fault_injection::run_crash_test;
+pub use file_system::FileOptions;
+pub use file_system::FileSystem;
+pub use file_system::RandomAccessFile;
+pub use file_system::WritableFile;
+pub use memory::MemFileSystem;
+pub use posix::PosixFileSystem;
+pub use rate_limiter::IoPriority;
+pub use rate_limiter::RateLimiter;
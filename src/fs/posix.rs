@@ -0,0 +1,428 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::bail;
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use super::FileOptions;
+use super::FileSystem;
+use super::RandomAccessFile;
+use super::WritableFile;
+use super::aligned_buf::AlignedBuf;
+use super::aligned_buf::DIRECT_IO_ALIGNMENT;
+use super::aligned_buf::align_down;
+use super::aligned_buf::align_up;
+
+// size of the aligned buffer used to write a file with direct I/O
+const DIRECT_IO_WRITE_BUFFER_SIZE: usize = 1 << 20;
+
+// File system on the local disk
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PosixFileSystem;
+
+#[cfg(target_os = "linux")]
+fn set_direct_io(options: &mut OpenOptions) -> Result<()> {
+    options.custom_flags(libc::O_DIRECT);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_direct_io(_options: &mut OpenOptions) -> Result<()> {
+    bail!("direct I/O is only supported on linux")
+}
+
+impl FileSystem for PosixFileSystem {
+    fn create(&self, path: &Path, options: FileOptions) -> Result<Box<dyn WritableFile>> {
+        let mut open_options = File::options();
+        open_options.write(true).create(true).truncate(true);
+        if options.use_direct_writes {
+            set_direct_io(&mut open_options)?;
+            let file = open_options
+                .open(path)
+                .with_context(|| format!("create file {:?}", path))?;
+            return Ok(Box::new(DirectWritableFile::new(file)));
+        }
+        let file = open_options
+            .open(path)
+            .with_context(|| format!("create file {:?}", path))?;
+        Ok(Box::new(PosixWritableFile {
+            writer: BufWriter::new(file),
+        }))
+    }
+
+    fn append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = File::options()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open file {:?} for appending", path))?;
+        Ok(Box::new(PosixWritableFile {
+            writer: BufWriter::new(file),
+        }))
+    }
+
+    fn open(&self, path: &Path, options: FileOptions) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(PosixRandomAccessFile::open(path, options)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::rename(from, to).with_context(|| format!("rename {:?} to {:?}", from, to))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(path).with_context(|| format!("remove file {:?}", path))
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::hard_link(from, to).with_context(|| format!("link {:?} to {:?}", from, to))
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("list {:?}", dir))? {
+            paths.push(entry?.path());
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path).with_context(|| format!("create directory {:?}", path))
+    }
+
+    fn sync_dir(&self, dir: &Path) -> Result<()> {
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+struct PosixWritableFile {
+    writer: BufWriter<File>,
+}
+
+impl WritableFile for PosixWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+// Writes through an aligned buffer, full buffers are written at aligned offsets.
+// The partial tail is written with zero padding which is truncated afterwards,
+// and written again at the same offset when more data is appended.
+struct DirectWritableFile {
+    file: File,
+    buf: AlignedBuf,
+    // length of data in buffer
+    buf_len: usize,
+    // aligned file offset of the buffer
+    offset: usize,
+}
+
+impl DirectWritableFile {
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            buf: AlignedBuf::new(DIRECT_IO_WRITE_BUFFER_SIZE),
+            buf_len: 0,
+            offset: 0,
+        }
+    }
+
+    fn write_tail(&mut self) -> Result<()> {
+        if self.buf_len > 0 {
+            let aligned_len = align_up(self.buf_len, DIRECT_IO_ALIGNMENT);
+            self.buf[self.buf_len..aligned_len].fill(0);
+            self.file
+                .write_all_at(&self.buf[..aligned_len], self.offset as u64)?;
+        }
+        self.file.set_len((self.offset + self.buf_len) as u64)?;
+        Ok(())
+    }
+}
+
+impl WritableFile for DirectWritableFile {
+    fn append(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let n = data.len().min(self.buf.len() - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+            if self.buf_len == self.buf.len() {
+                self.file.write_all_at(&self.buf, self.offset as u64)?;
+                self.offset += self.buf.len();
+                self.buf_len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.write_tail()?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Drop for DirectWritableFile {
+    fn drop(&mut self) {
+        // the same as `BufWriter`, buffered data is written and errors are ignored
+        let _ = self.write_tail();
+    }
+}
+
+pub(crate) struct PosixRandomAccessFile {
+    file: File,
+    // the mapping of the whole file if `FileOptions::use_mmap` is set
+    mmap: Option<Bytes>,
+    // the file is opened with O_DIRECT
+    direct: bool,
+    size: usize,
+}
+
+impl PosixRandomAccessFile {
+    fn open(path: &Path, options: FileOptions) -> Result<Self> {
+        if options.use_mmap && options.use_direct_reads {
+            bail!("mmap reads and direct reads can not be both enabled");
+        }
+        let mut open_options = File::options();
+        open_options.read(true).write(false);
+        if options.use_direct_reads {
+            set_direct_io(&mut open_options)?;
+        }
+        let file = open_options
+            .open(path)
+            .with_context(|| format!("open file {:?}", path))?;
+        let size = file.metadata()?.len() as usize;
+        // empty files can not be mapped
+        let mmap = if options.use_mmap && size > 0 {
+            // Safety: sstables are immutable once written, they are only removed
+            // and never modified or truncated while opened.
+            let mmap = unsafe { Mmap::map(&file)? };
+            Some(Bytes::from_owner(mmap))
+        } else {
+            None
+        };
+        Ok(Self {
+            file,
+            mmap,
+            direct: options.use_direct_reads,
+            size,
+        })
+    }
+
+    // offset and length of direct reads must be aligned, so read the aligned range
+    // covering [offset, offset + len) and copy out the requested part
+    fn read_direct(&self, offset: usize, len: usize) -> Result<Bytes> {
+        let aligned_offset = align_down(offset, DIRECT_IO_ALIGNMENT);
+        let end = offset + len - aligned_offset;
+        let mut buf = AlignedBuf::new(end);
+        let mut read = 0;
+        // the last aligned block may be partially filled at the end of file
+        while read < end {
+            let n = self
+                .file
+                .read_at(&mut buf[read..], (aligned_offset + read) as u64)?;
+            if n == 0 {
+                bail!("read [{}, {}) reaches end of file", offset, offset + len);
+            }
+            read += n;
+        }
+        let start = offset - aligned_offset;
+        Ok(Bytes::copy_from_slice(&buf[start..start + len]))
+    }
+}
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    fn read(&self, offset: u64, len: u64) -> Result<Bytes> {
+        let (offset, len) = (offset as usize, len as usize);
+        if let Some(mmap) = &self.mmap {
+            if offset + len > mmap.len() {
+                bail!("read [{}, {}) reaches end of file", offset, offset + len);
+            }
+            return Ok(mmap.slice(offset..offset + len));
+        }
+        if self.direct {
+            return self.read_direct(offset, len);
+        }
+
+        let mut data = vec![0; len];
+        self.file.read_exact_at(&mut data[..], offset as u64)?;
+        Ok(data.into())
+    }
+
+    // the reads are submitted together with io_uring if the `io-uring` feature
+    // is enabled, otherwise they are read one by one
+    fn read_batch(&self, ranges: &[(u64, u64)]) -> Result<Vec<Bytes>> {
+        #[cfg(feature = "io-uring")]
+        if self.mmap.is_none() && !self.direct && ranges.len() > 1 {
+            let ranges: Vec<(u64, usize)> = ranges
+                .iter()
+                .map(|(offset, len)| (*offset, *len as usize))
+                .collect();
+            if let Some(result) = super::uring::read_batch(&self.file, &ranges) {
+                return Ok(result?.into_iter().map(Bytes::from).collect());
+            }
+        }
+
+        ranges
+            .iter()
+            .map(|(offset, len)| self.read(*offset, *len))
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::PosixFileSystem;
+    use super::PosixRandomAccessFile;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+    use crate::fs::RandomAccessFile;
+
+    fn write_file(path: &std::path::Path, data: &[u8], options: FileOptions) -> Result<()> {
+        let mut file = PosixFileSystem.create(path, options)?;
+        // append in pieces to cross the boundaries of aligned buffer
+        for chunk in data.chunks(3000) {
+            file.append(chunk)?;
+        }
+        file.sync()
+    }
+
+    #[test]
+    fn test_read_modes() -> Result<()> {
+        let dir = tempdir()?;
+        let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        let path = dir.path().join("data");
+        write_file(&path, &data, FileOptions::default())?;
+        for (use_mmap, use_direct_reads) in [(false, false), (true, false), (false, true)] {
+            let options = FileOptions {
+                use_mmap,
+                use_direct_reads,
+                ..FileOptions::default()
+            };
+            let file = PosixRandomAccessFile::open(&path, options)?;
+            assert_eq!(file.mmap.is_some(), use_mmap);
+            assert_eq!(file.direct, use_direct_reads);
+            assert_eq!(file.size(), data.len());
+            for (offset, len) in [(0, 10), (4000, 200), (4096, 4096), (9993, 7)] {
+                assert_eq!(
+                    file.read(offset as u64, len as u64)?.as_ref(),
+                    &data[offset..offset + len]
+                );
+            }
+            assert!(file.read(9999, 2).is_err());
+
+            let ranges = [(0, 10), (5000, 4000), (9999, 1), (100, 0)];
+            let result = file.read_batch(&ranges)?;
+            assert_eq!(result.len(), ranges.len());
+            for ((offset, len), buf) in ranges.iter().zip(result) {
+                assert_eq!(
+                    buf.as_ref(),
+                    &data[*offset as usize..(offset + len) as usize]
+                );
+            }
+        }
+
+        // mmap and direct reads are exclusive
+        let options = FileOptions {
+            use_mmap: true,
+            use_direct_reads: true,
+            ..FileOptions::default()
+        };
+        assert!(PosixFileSystem.open(&path, options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_direct_writes() -> Result<()> {
+        let dir = tempdir()?;
+        let options = FileOptions {
+            use_direct_writes: true,
+            ..FileOptions::default()
+        };
+        // not a multiple of the alignment, and larger than the write buffer
+        let data: Vec<u8> = (0..(1 << 20) + 5000).map(|i| (i % 251) as u8).collect();
+        let path = dir.path().join("direct");
+        write_file(&path, &data, options)?;
+        assert_eq!(std::fs::read(&path)?, data);
+
+        // append after sync rewrites the padded tail
+        let mut file = PosixFileSystem.create(&path, options)?;
+        file.append(&data[..100])?;
+        file.sync()?;
+        assert_eq!(std::fs::read(&path)?, &data[..100]);
+        file.append(&data[100..5000])?;
+        file.sync()?;
+        assert_eq!(std::fs::read(&path)?, &data[..5000]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_operations() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = PosixFileSystem;
+        let sub_dir = dir.path().join("a/b");
+        fs.create_dir_all(&sub_dir)?;
+        assert!(fs.exists(&sub_dir));
+
+        let path = sub_dir.join("1");
+        write_file(&path, b"hello", FileOptions::default())?;
+        let mut file = fs.append(&path)?;
+        file.append(b" world")?;
+        file.sync()?;
+        assert_eq!(fs.read_all(&path)?.as_ref(), b"hello world");
+
+        fs.hard_link(&path, &sub_dir.join("2"))?;
+        fs.rename(&path, &sub_dir.join("3"))?;
+        assert_eq!(fs.list(&sub_dir)?, vec![
+            sub_dir.join("2"),
+            sub_dir.join("3")
+        ]);
+        fs.remove(&sub_dir.join("3"))?;
+        assert!(!fs.exists(&sub_dir.join("3")));
+        assert_eq!(fs.read_all(&sub_dir.join("2"))?.as_ref(), b"hello world");
+        assert!(fs.remove(&sub_dir.join("3")).is_err());
+        fs.sync_dir(&sub_dir)?;
+        Ok(())
+    }
+}
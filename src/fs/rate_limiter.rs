@@ -0,0 +1,281 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use parking_lot::Condvar;
+use parking_lot::Mutex;
+
+use super::WritableFile;
+
+// the rate is tuned once every this many refill periods
+const TUNE_REFILL_PERIODS: u32 = 100;
+// auto tuned rate is kept in [max rate / MIN_RATE_DIVISOR, max rate]
+const MIN_RATE_DIVISOR: usize = 20;
+// the rate is raised if the tokens run out in more than this percent of refill
+// periods, lowered if less than the low one
+const HIGH_DRAINED_PERCENT: usize = 90;
+const LOW_DRAINED_PERCENT: usize = 50;
+// percent the rate changes by in one tuning
+const ADJUST_PERCENT: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IoPriority {
+    // flush, which blocks writes if it falls behind
+    High,
+    // compaction and ingestion
+    Low,
+}
+
+impl IoPriority {
+    fn index(self) -> usize {
+        match self {
+            IoPriority::High => 0,
+            IoPriority::Low => 1,
+        }
+    }
+}
+
+struct RateLimiterState {
+    rate_bytes_per_sec: usize,
+    // tokens left in this refill period
+    available: usize,
+    next_refill: Instant,
+    // number of requests waiting for tokens, by priority
+    waiting: [usize; 2],
+    total_bytes: [u64; 2],
+
+    // tokens ran out in this refill period
+    drained: bool,
+    num_refills: usize,
+    num_drained: usize,
+    tune_start: Instant,
+}
+
+// Token bucket shared by the background writers. Tokens of one refill period are
+// added at the start of each period, writers wait when they run out. Low priority
+// requests wait while any high priority request is waiting, so flush is not
+// starved by compaction.
+pub struct RateLimiter {
+    refill_period: Duration,
+    // with auto tuning, the rate follows the demand of the writers
+    // between the max rate and 1/20 of it
+    auto_tuned: bool,
+    max_rate_bytes_per_sec: usize,
+    state: Mutex<RateLimiterState>,
+    cond: Condvar,
+}
+
+impl RateLimiter {
+    pub fn new(rate_bytes_per_sec: usize) -> Self {
+        Self::create(rate_bytes_per_sec, Duration::from_millis(100), false)
+    }
+
+    pub fn create(rate_bytes_per_sec: usize, refill_period: Duration, auto_tuned: bool) -> Self {
+        let now = Instant::now();
+        let rate_bytes_per_sec = std::cmp::max(rate_bytes_per_sec, 1);
+        Self {
+            refill_period,
+            auto_tuned,
+            max_rate_bytes_per_sec: rate_bytes_per_sec,
+            state: Mutex::new(RateLimiterState {
+                rate_bytes_per_sec,
+                available: Self::bytes_per_period(rate_bytes_per_sec, refill_period),
+                next_refill: now + refill_period,
+                waiting: [0; 2],
+                total_bytes: [0; 2],
+                drained: false,
+                num_refills: 0,
+                num_drained: 0,
+                tune_start: now,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn rate_bytes_per_sec(&self) -> usize {
+        self.state.lock().rate_bytes_per_sec
+    }
+
+    pub fn set_rate_bytes_per_sec(&self, rate_bytes_per_sec: usize) {
+        self.state.lock().rate_bytes_per_sec = std::cmp::max(rate_bytes_per_sec, 1);
+    }
+
+    // total bytes granted to the requests of `priority`
+    pub fn total_bytes_through(&self, priority: IoPriority) -> u64 {
+        self.state.lock().total_bytes[priority.index()]
+    }
+
+    // block until `bytes` tokens are granted, large requests are granted
+    // in pieces of at most one refill period
+    pub fn request(&self, bytes: usize, priority: IoPriority) {
+        let mut state = self.state.lock();
+        let mut remaining = bytes;
+        while remaining > 0 {
+            state.waiting[priority.index()] += 1;
+            let granted = loop {
+                self.refill(&mut state);
+                // the burst changes if the rate is changed while waiting
+                let bytes = std::cmp::min(remaining, self.burst_bytes(&state));
+                let yielding = priority == IoPriority::Low && state.waiting[0] > 0;
+                if !yielding && state.available >= bytes {
+                    break bytes;
+                }
+                if state.available < bytes {
+                    state.drained = true;
+                }
+                let deadline = state.next_refill;
+                self.cond.wait_until(&mut state, deadline);
+            };
+            state.waiting[priority.index()] -= 1;
+            state.available -= granted;
+            state.total_bytes[priority.index()] += granted as u64;
+            remaining -= granted;
+            // wake the low priority requests yielding to this one
+            self.cond.notify_all();
+        }
+    }
+
+    // append `data` to `file`, requesting tokens before each piece is written
+    pub fn append(
+        &self,
+        file: &mut dyn WritableFile,
+        data: &[u8],
+        priority: IoPriority,
+    ) -> Result<()> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = std::cmp::min(data.len() - offset, self.single_burst_bytes());
+            self.request(len, priority);
+            file.append(&data[offset..offset + len])?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    // max number of bytes granted in one refill period
+    pub fn single_burst_bytes(&self) -> usize {
+        self.burst_bytes(&self.state.lock())
+    }
+
+    fn burst_bytes(&self, state: &RateLimiterState) -> usize {
+        Self::bytes_per_period(state.rate_bytes_per_sec, self.refill_period)
+    }
+
+    fn bytes_per_period(rate_bytes_per_sec: usize, refill_period: Duration) -> usize {
+        let bytes = rate_bytes_per_sec as u128 * refill_period.as_micros() / 1_000_000;
+        std::cmp::max(bytes as usize, 1)
+    }
+
+    fn refill(&self, state: &mut RateLimiterState) {
+        let now = Instant::now();
+        if now < state.next_refill {
+            return;
+        }
+        // tokens do not pile up while no one is writing
+        state.available = self.burst_bytes(state);
+        state.next_refill = now + self.refill_period;
+        state.num_refills += 1;
+        if std::mem::take(&mut state.drained) {
+            state.num_drained += 1;
+        }
+
+        if self.auto_tuned && now >= state.tune_start + self.refill_period * TUNE_REFILL_PERIODS {
+            self.tune(state);
+            state.tune_start = now;
+            state.num_refills = 0;
+            state.num_drained = 0;
+        }
+    }
+
+    fn tune(&self, state: &mut RateLimiterState) {
+        let drained_percent = state.num_drained * 100 / std::cmp::max(state.num_refills, 1);
+        let rate = state.rate_bytes_per_sec;
+        let rate = if drained_percent > HIGH_DRAINED_PERCENT {
+            rate.saturating_mul(100 + ADJUST_PERCENT) / 100
+        } else if drained_percent < LOW_DRAINED_PERCENT {
+            rate.saturating_mul(100) / (100 + ADJUST_PERCENT)
+        } else {
+            rate
+        };
+        let min_rate = std::cmp::max(self.max_rate_bytes_per_sec / MIN_RATE_DIVISOR, 1);
+        state.rate_bytes_per_sec = rate.clamp(min_rate, self.max_rate_bytes_per_sec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::IoPriority;
+    use super::RateLimiter;
+
+    #[test]
+    fn test_rate_limiter() {
+        // 10KB every 10ms
+        let limiter = RateLimiter::create(1 << 20, Duration::from_millis(10), false);
+        assert_eq!(limiter.single_burst_bytes(), 10485);
+
+        let start = Instant::now();
+        limiter.request(200 << 10, IoPriority::Low);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(limiter.total_bytes_through(IoPriority::Low), 200 << 10);
+        assert_eq!(limiter.total_bytes_through(IoPriority::High), 0);
+
+        // low priority requests yield to the high priority ones
+        let limiter = Arc::new(RateLimiter::create(
+            100 << 10,
+            Duration::from_millis(10),
+            false,
+        ));
+        let low = {
+            let limiter = limiter.clone();
+            std::thread::spawn(move || {
+                limiter.request(40 << 10, IoPriority::Low);
+                Instant::now()
+            })
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        limiter.request(20 << 10, IoPriority::High);
+        let high_finished = Instant::now();
+        assert!(high_finished < low.join().unwrap());
+    }
+
+    #[test]
+    fn test_auto_tuned_rate_limiter() {
+        let max_rate = 10 << 20;
+        let limiter = RateLimiter::create(max_rate, Duration::from_millis(1), true);
+
+        // lowered when there are few writes
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(110));
+            limiter.request(1, IoPriority::Low);
+        }
+        let lowered = limiter.rate_bytes_per_sec();
+        assert!(lowered < max_rate);
+
+        // raised when the tokens keep running out
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(350) {
+            limiter.request(limiter.single_burst_bytes(), IoPriority::Low);
+        }
+        let raised = limiter.rate_bytes_per_sec();
+        assert!(raised > lowered);
+        assert!(raised <= max_rate);
+    }
+}
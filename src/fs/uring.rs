@@ -0,0 +1,142 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+use anyhow::Result;
+use io_uring::IoUring;
+use io_uring::opcode;
+use io_uring::types;
+
+// number of reads submitted to the ring at once
+const RING_ENTRIES: u32 = 64;
+
+thread_local! {
+    // every thread owns one ring, None if io_uring is not supported by the kernel
+    static RING: RefCell<Option<IoUring>> = RefCell::new(IoUring::new(RING_ENTRIES).ok());
+}
+
+// read all the (offset, len) ranges of the file with io_uring,
+// return None if io_uring is unavailable so that callers fall back to pread
+pub(crate) fn read_batch(file: &File, ranges: &[(u64, usize)]) -> Option<Result<Vec<Vec<u8>>>> {
+    RING.with(|slot| {
+        let mut slot = slot.borrow_mut();
+        let ring = slot.as_mut()?;
+
+        let mut bufs: Vec<Vec<u8>> = ranges.iter().map(|(_, len)| vec![0; *len]).collect();
+        let mut read = vec![0; ranges.len()];
+        for start in (0..ranges.len()).step_by(RING_ENTRIES as usize) {
+            let end = (start + RING_ENTRIES as usize).min(ranges.len());
+            if let Err(err) = submit_and_reap(ring, file, ranges, &mut bufs, &mut read, start..end)
+            {
+                // reads may be still in flight, leak the buffers and the ring
+                // instead of letting the kernel write into freed memory
+                std::mem::forget(bufs);
+                std::mem::forget(slot.take());
+                return Some(Err(err.into()));
+            }
+        }
+        if let Some(errno) = read.iter().find(|result| **result < 0) {
+            return Some(Err(io::Error::from_raw_os_error(-*errno as i32).into()));
+        }
+
+        // finish short reads synchronously
+        for (i, (offset, len)) in ranges.iter().enumerate() {
+            let done = read[i] as usize;
+            if done < *len {
+                if let Err(err) = file.read_exact_at(&mut bufs[i][done..], offset + done as u64) {
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+        Some(Ok(bufs))
+    })
+}
+
+// submit reads of `range` and wait for all of them to complete, the result
+// (bytes read or negative errno) of each read is saved in `read`
+fn submit_and_reap(
+    ring: &mut IoUring,
+    file: &File,
+    ranges: &[(u64, usize)],
+    bufs: &mut [Vec<u8>],
+    read: &mut [i64],
+    range: std::ops::Range<usize>,
+) -> io::Result<()> {
+    let fd = types::Fd(file.as_raw_fd());
+    for i in range.clone() {
+        let entry = opcode::Read::new(fd, bufs[i].as_mut_ptr(), bufs[i].len() as u32)
+            .offset(ranges[i].0)
+            .build()
+            .user_data(i as u64);
+        // Safety: the buffer is alive until the read is completed
+        unsafe {
+            ring.submission()
+                .push(&entry)
+                .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        }
+    }
+
+    let mut pending = range.len();
+    while pending > 0 {
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+        for cqe in ring.completion() {
+            read[cqe.user_data() as usize] = cqe.result() as i64;
+            pending -= 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::read_batch;
+
+    #[test]
+    fn test_read_batch() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("data");
+        let data: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data)?;
+        let file = File::open(&path)?;
+
+        // more ranges than the ring entries
+        let ranges: Vec<(u64, usize)> = (0..200).map(|i| (i * 400, 100 + i as usize)).collect();
+        let Some(bufs) = read_batch(&file, &ranges) else {
+            // io_uring is not supported
+            return Ok(());
+        };
+        for ((offset, len), buf) in ranges.iter().zip(bufs?) {
+            assert_eq!(&data[*offset as usize..*offset as usize + len], &buf[..]);
+        }
+
+        // reading beyond the end of file fails
+        let result = read_batch(&file, &[(data.len() as u64 - 10, 20)]).unwrap();
+        assert!(result.is_err());
+        Ok(())
+    }
+}
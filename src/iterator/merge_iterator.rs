@@ -0,0 +1,196 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::binary_heap::PeekMut;
+
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::base::KeySlice;
+
+struct HeapWrapper<I: StorageIterator>(usize, I);
+
+impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<I: StorageIterator> Eq for HeapWrapper<I> {}
+
+impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<I: StorageIterator> Ord for HeapWrapper<I> {
+    // reversed, so that the max heap pops the smallest key first
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.1
+            .key()
+            .cmp(&other.1.key())
+            .then(self.0.cmp(&other.0))
+            .reverse()
+    }
+}
+
+// Merge sorted iterators into one sorted iterator. If the same key with the same
+// version is in more than one iterator, only the one in the iterator with the
+// smallest index is returned.
+pub struct MergeIterator<I: StorageIterator> {
+    iters: BinaryHeap<HeapWrapper<I>>,
+    current: Option<HeapWrapper<I>>,
+}
+
+impl<I: StorageIterator> MergeIterator<I> {
+    pub fn create(iters: Vec<I>) -> Self {
+        let mut heap: BinaryHeap<HeapWrapper<I>> = iters
+            .into_iter()
+            .enumerate()
+            .filter(|(_, iter)| iter.is_valid())
+            .map(|(idx, iter)| HeapWrapper(idx, iter))
+            .collect();
+        let current = heap.pop();
+        Self {
+            iters: heap,
+            current,
+        }
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
+    fn key(&self) -> KeySlice<'_> {
+        // safe to unwrap, only called when the iterator is valid
+        self.current.as_ref().unwrap().1.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.value()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let Some(mut current) = self.current.take() else {
+            return Ok(());
+        };
+
+        // skip the same key in the other iterators
+        while let Some(mut top) = self.iters.peek_mut() {
+            if top.1.key() != current.1.key() {
+                break;
+            }
+            if let Err(err) = top.1.next() {
+                PeekMut::pop(top);
+                return Err(err);
+            }
+            if !top.1.is_valid() {
+                PeekMut::pop(top);
+            }
+        }
+
+        current.1.next()?;
+        if current.1.is_valid() {
+            self.iters.push(current);
+        }
+        self.current = self.iters.pop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::MergeIterator;
+    use crate::base::KeySlice;
+    use crate::iterator::StorageIterator;
+
+    struct VecIterator {
+        data: Vec<(Vec<u8>, u64, Vec<u8>)>,
+        idx: usize,
+    }
+
+    impl VecIterator {
+        fn new(data: &[(&str, u64, &str)]) -> Self {
+            Self {
+                data: data
+                    .iter()
+                    .map(|(key, version, value)| {
+                        (key.as_bytes().to_vec(), *version, value.as_bytes().to_vec())
+                    })
+                    .collect(),
+                idx: 0,
+            }
+        }
+    }
+
+    impl StorageIterator for VecIterator {
+        fn key(&self) -> KeySlice<'_> {
+            let (key, version, _) = &self.data[self.idx];
+            KeySlice::from_slice(key, *version)
+        }
+
+        fn value(&self) -> &[u8] {
+            &self.data[self.idx].2
+        }
+
+        fn is_valid(&self) -> bool {
+            self.idx < self.data.len()
+        }
+
+        fn next(&mut self) -> Result<()> {
+            self.idx += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_merge_iterator() -> Result<()> {
+        let mut iter = MergeIterator::create(vec![
+            VecIterator::new(&[("a", 2, "a2"), ("c", 1, "c1_new")]),
+            VecIterator::new(&[]),
+            VecIterator::new(&[("a", 1, "a1"), ("b", 1, "b1"), ("c", 1, "c1_old")]),
+            VecIterator::new(&[("b", 3, "b3"), ("d", 1, "d1")]),
+        ]);
+
+        let mut result = Vec::new();
+        while iter.is_valid() {
+            result.push((
+                String::from_utf8(iter.key().key_ref().to_vec())?,
+                iter.key().version(),
+                String::from_utf8(iter.value().to_vec())?,
+            ));
+            iter.next()?;
+        }
+        let expected = [
+            ("a", 1, "a1"),
+            ("a", 2, "a2"),
+            ("b", 1, "b1"),
+            ("b", 3, "b3"),
+            ("c", 1, "c1_new"),
+            ("d", 1, "d1"),
+        ];
+        assert_eq!(result.len(), expected.len());
+        for ((key, version, value), expected) in result.iter().zip(expected) {
+            assert_eq!((key.as_str(), *version, value.as_str()), expected);
+        }
+        Ok(())
+    }
+}
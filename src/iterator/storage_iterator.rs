@@ -0,0 +1,31 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+
+use crate::base::KeySlice;
+
+// Iterator over sorted key-value pairs, keys are ordered by (key, version)
+pub trait StorageIterator {
+    // current key, only valid if `is_valid` returns true
+    fn key(&self) -> KeySlice<'_>;
+
+    // current value, only valid if `is_valid` returns true
+    fn value(&self) -> &[u8];
+
+    fn is_valid(&self) -> bool;
+
+    // move to the next position
+    fn next(&mut self) -> Result<()>;
+}
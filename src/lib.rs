@@ -12,11 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-mod base;
-mod block;
-mod compact;
-mod engine;
-mod memtable;
-mod mvcc;
-mod table;
-mod wal;
+pub mod base;
+pub mod block;
+pub mod compact;
+pub mod engine;
+pub mod fs;
+pub mod iterator;
+pub mod memtable;
+pub mod mvcc;
+pub mod table;
+pub mod wal;
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::ops::Bound;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
@@ -22,10 +24,18 @@ use crossbeam_skiplist::SkipMap;
 
 use crate::base::KeyBytes;
 use crate::base::KeySlice;
+use crate::base::VERSION_DEFAULT;
+use crate::base::Version;
+use crate::fs::FileSystem;
+use crate::table::SsTableBuilder;
+use crate::wal::Wal;
 
 pub struct Memtable {
     map: Arc<SkipMap<KeyBytes, Bytes>>,
 
+    // writes are saved into wal before inserting into the map, if any
+    wal: Option<Wal>,
+
     id: usize,
 
     // since `SkipMap` has no function such as `size()` to
@@ -37,25 +47,64 @@ impl Memtable {
     pub fn new(id: usize) -> Self {
         Self {
             map: Arc::new(SkipMap::new()),
+            wal: None,
             id,
             approximate_size: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    pub fn create_with_wal(id: usize, fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            wal: Some(Wal::create(fs, path)?),
+            ..Self::new(id)
+        })
+    }
+
     pub fn read(&self, key: KeySlice) -> Option<Bytes> {
-        let key = KeyBytes::new(
-            Bytes::from_static(unsafe { std::mem::transmute::<&[u8], &[u8]>(key.key_ref()) }),
-            key.version(),
-        );
+        let key = Self::static_key(key.key_ref(), key.version());
 
         self.map.get(&key).map(|entry| entry.value().clone())
     }
 
+    // return the value of the latest version of `key` not greater than `read_version`,
+    // an empty value means the key is deleted
+    pub fn get(&self, key: &[u8], read_version: Version) -> Option<Bytes> {
+        let lower = Self::static_key(key, VERSION_DEFAULT);
+        let upper = Self::static_key(key, read_version);
+
+        self.map
+            .range((Bound::Included(lower), Bound::Included(upper)))
+            .next_back()
+            .map(|entry| entry.value().clone())
+    }
+
+    // return true if there are keys in [first, last]
+    pub fn overlaps(&self, first: &[u8], last: &[u8]) -> bool {
+        let lower = Self::static_key(first, VERSION_DEFAULT);
+        let upper = Self::static_key(last, Version::MAX);
+
+        self.map
+            .range((Bound::Included(lower), Bound::Included(upper)))
+            .next()
+            .is_some()
+    }
+
+    // the returned key MUST not outlive `key`, only used to lookup the map
+    fn static_key(key: &[u8], version: Version) -> KeyBytes {
+        KeyBytes::new(
+            Bytes::from_static(unsafe { std::mem::transmute::<&[u8], &[u8]>(key) }),
+            version,
+        )
+    }
+
     pub fn write(&self, key: KeySlice, value: &[u8]) -> Result<()> {
         self.write_batch(&[(key, value)])
     }
 
     pub fn write_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.write_batch(data)?;
+        }
         let mut est_size = 0;
         for (k, v) in data {
             est_size += k.raw_len() + v.len();
@@ -65,6 +114,25 @@ impl Memtable {
         Ok(())
     }
 
+    pub fn sync_wal(&self) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.sync()?;
+        }
+        Ok(())
+    }
+
+    // add all the key-value pairs into the sstable builder
+    pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
+        for entry in self.map.iter() {
+            builder.add(entry.key().to_key_slice(), entry.value())?;
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     pub fn size(&self) -> usize {
         self.approximate_size.load(Ordering::Relaxed)
     }
@@ -95,4 +163,26 @@ mod tests {
 
         assert_eq!(table.size(), key.raw_len() + value.len());
     }
+
+    #[test]
+    fn test_get_with_version() {
+        let table = Memtable::new(1);
+        for version in [2, 4, 6] {
+            let key = KeyBytes::new(Bytes::from("hello"), version);
+            let value = format!("world_{}", version);
+            assert!(table.write(key.to_key_slice(), value.as_bytes()).is_ok());
+        }
+
+        assert!(table.get(b"hello", 1).is_none());
+        assert_eq!(table.get(b"hello", 2), Some(Bytes::from("world_2")));
+        assert_eq!(table.get(b"hello", 5), Some(Bytes::from("world_4")));
+        assert_eq!(table.get(b"hello", u64::MAX), Some(Bytes::from("world_6")));
+        assert!(table.get(b"hell", u64::MAX).is_none());
+        assert!(table.get(b"hello!", u64::MAX).is_none());
+
+        assert!(table.overlaps(b"a", b"hello"));
+        assert!(table.overlaps(b"hello", b"z"));
+        assert!(!table.overlaps(b"a", b"hell"));
+        assert!(!table.overlaps(b"hello!", b"z"));
+    }
 }
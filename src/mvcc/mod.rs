@@ -17,4 +17,5 @@ mod txn;
 mod watermark;
 
 pub use mvcc_inner::MvccInner;
+pub use txn::Transaction;
 pub use watermark::Watermark;
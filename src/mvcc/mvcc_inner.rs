@@ -46,6 +46,14 @@ impl MvccInner {
         }
     }
 
+    pub fn latest_version(&self) -> Version {
+        self.version.lock().0
+    }
+
+    pub fn update_latest_version(&self, version: Version) {
+        self.version.lock().0 = version;
+    }
+
     /// All version(strictly) below this version can be garbage collected.
     pub fn watermark(&self) -> Version {
         let version = self.version.lock();
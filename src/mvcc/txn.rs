@@ -23,6 +23,7 @@ use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use super::mvcc_inner::CommittedTxn;
+use crate::base::Error;
 use crate::base::Version;
 use crate::engine::LsmEngineInner;
 use crate::engine::WriteBatchRecord;
@@ -0,0 +1,74 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use anyhow::Result;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use super::BlockMetaVec;
+use super::SsTableId;
+use crate::block::Block;
+
+// Entries in the block cache, index partitions share the cache with data blocks
+// so that the memory of opened sstables is bounded by `block_cache_num`.
+#[derive(Clone)]
+pub enum CachedBlock {
+    Data(Arc<Block>),
+    IndexPartition(Arc<BlockMetaVec>),
+}
+
+// LRU cache of blocks, keyed by (sstable id, block offset)
+pub struct BlockCache {
+    cache: Mutex<LruCache<(SsTableId, usize), CachedBlock>>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn get(&self, id: SsTableId, offset: usize) -> Option<CachedBlock> {
+        self.cache.lock().get(&(id, offset)).cloned()
+    }
+
+    pub fn insert(&self, id: SsTableId, offset: usize, block: CachedBlock) {
+        self.cache.lock().put((id, offset), block);
+    }
+
+    // return the cached block, or load it with `load` and insert it into the cache.
+    // the lock is not held while loading, so concurrent readers may load the same block.
+    pub fn get_or_load<F>(&self, id: SsTableId, offset: usize, load: F) -> Result<CachedBlock>
+    where F: FnOnce() -> Result<CachedBlock> {
+        if let Some(block) = self.get(id, offset) {
+            return Ok(block);
+        }
+        let block = load()?;
+        self.insert(id, offset, block.clone());
+        Ok(block)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.lock().is_empty()
+    }
+}
@@ -18,6 +18,8 @@ use bytes::Buf;
 use bytes::BufMut;
 
 use crate::base::KeyBytes;
+use crate::base::KeySlice;
+use crate::base::VERSION_DEFAULT;
 use crate::base::Version;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -30,6 +32,10 @@ pub struct BlockMeta {
 
     // The last key of block
     pub last_key: KeyBytes,
+
+    // The min and max version of keys in the block
+    pub min_version: Version,
+    pub max_version: Version,
 }
 
 impl BlockMeta {
@@ -43,26 +49,39 @@ impl BlockMeta {
         estimated_size += self.first_key.raw_len();
         // The size of last key
         estimated_size += self.last_key.raw_len();
+        // The size of min and max version
+        estimated_size += std::mem::size_of::<u64>() * 2;
 
         estimated_size
     }
 
+    // return true if the block may contain keys with version in [min_version, max_version]
+    pub fn overlaps_versions(&self, min_version: Version, max_version: Version) -> bool {
+        self.min_version <= max_version && self.max_version >= min_version
+    }
+
     pub fn encode(&self, buf: &mut Vec<u8>) {
         buf.put_u32(self.offset as u32);
         self.first_key.encode(buf);
         self.last_key.encode(buf);
+        buf.put_u64(self.min_version);
+        buf.put_u64(self.max_version);
     }
 
     pub fn decode(mut buf: &[u8]) -> (Self, &[u8]) {
         let offset = buf.get_u32() as usize;
         let (first_key, buf) = KeyBytes::decode(buf);
-        let (last_key, buf) = KeyBytes::decode(buf);
+        let (last_key, mut buf) = KeyBytes::decode(buf);
+        let min_version = buf.get_u64();
+        let max_version = buf.get_u64();
 
         (
             Self {
                 offset,
                 first_key,
                 last_key,
+                min_version,
+                max_version,
             },
             buf,
         )
@@ -85,7 +104,54 @@ impl BlockMetaVec {
         self.0.push(meta);
     }
 
-    pub fn encode(&self, version: Version, buf: &mut Vec<u8>) {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> &BlockMeta {
+        &self.0[idx]
+    }
+
+    pub fn first(&self) -> Option<&BlockMeta> {
+        self.0.first()
+    }
+
+    pub fn last(&self) -> Option<&BlockMeta> {
+        self.0.last()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockMeta> {
+        self.0.iter()
+    }
+
+    pub fn min_version(&self) -> Version {
+        self.0
+            .iter()
+            .map(|meta| meta.min_version)
+            .min()
+            .unwrap_or(VERSION_DEFAULT)
+    }
+
+    pub fn max_version(&self) -> Version {
+        self.0
+            .iter()
+            .map(|meta| meta.max_version)
+            .max()
+            .unwrap_or(VERSION_DEFAULT)
+    }
+
+    // return the index of the first block whose last key >= `key`,
+    // return `len()` if all the blocks are less than `key`
+    pub fn search(&self, key: KeySlice) -> usize {
+        self.0
+            .partition_point(|meta| meta.last_key.to_key_slice() < key)
+    }
+
+    pub fn estimated_size(&self) -> usize {
         // number of blocks
         let mut estimated_size = std::mem::size_of::<u32>();
         for meta in &self.0 {
@@ -96,6 +162,12 @@ impl BlockMetaVec {
         // checksum
         estimated_size += std::mem::size_of::<u32>();
 
+        estimated_size
+    }
+
+    pub fn encode(&self, version: Version, buf: &mut Vec<u8>) {
+        let estimated_size = self.estimated_size();
+
         buf.reserve(estimated_size);
         let original_len = buf.len();
         buf.put_u32(self.0.len() as u32);
@@ -146,6 +218,8 @@ mod tests {
             first_key,
             last_key,
             offset,
+            min_version: 1,
+            max_version: 12,
         };
 
         let estimated_size = meta.estimated_size();
@@ -171,6 +245,8 @@ mod tests {
                 first_key,
                 last_key,
                 offset,
+                min_version: 1,
+                max_version: 12,
             };
             meta_vec.push(meta);
         }
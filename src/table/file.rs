@@ -12,46 +12,146 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::File;
-use std::os::unix::fs::FileExt;
 use std::path::Path;
 
 use anyhow::Result;
+use anyhow::bail;
+use bytes::Bytes;
+
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::IoPriority;
+use crate::fs::RandomAccessFile;
+use crate::fs::RateLimiter;
 
 pub struct FileObject {
-    file: Option<File>,
+    file: Box<dyn RandomAccessFile>,
     size: usize,
 }
 
 impl FileObject {
-    pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
-        let mut data = vec![0; len as usize];
-        self.file
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
+    pub fn read(&self, offset: u64, len: u64) -> Result<Bytes> {
+        self.check_range(offset, len)?;
+        let data = self.file.read(offset, len)?;
+        Self::check_read(offset, len, &data)?;
         Ok(data)
     }
 
+    // read the (offset, len) ranges, the file system may submit them together
+    pub fn read_batch(&self, ranges: &[(u64, u64)]) -> Result<Vec<Bytes>> {
+        for (offset, len) in ranges {
+            self.check_range(*offset, *len)?;
+        }
+        let result = self.file.read_batch(ranges)?;
+        for ((offset, len), data) in ranges.iter().zip(&result) {
+            Self::check_read(*offset, *len, data)?;
+        }
+        Ok(result)
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> Result<()> {
+        if offset + len > self.size as u64 {
+            bail!(
+                "read [{}, {}) is out of file size {}",
+                offset,
+                offset + len,
+                self.size
+            );
+        }
+        Ok(())
+    }
+
+    // the file system must return all the bytes asked for
+    fn check_read(offset: u64, len: u64, data: &Bytes) -> Result<()> {
+        if data.len() as u64 != len {
+            bail!(
+                "short read at {}, expect {} bytes but got {}",
+                offset,
+                len,
+                data.len()
+            );
+        }
+        Ok(())
+    }
+
     pub fn size(&self) -> usize {
         self.size
     }
 
-    pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
-        Ok(FileObject {
-            file: Some(File::options().read(true).write(false).open(path)?),
-            size: data.len(),
-        })
+    pub fn create(
+        fs: &dyn FileSystem,
+        path: &Path,
+        data: Vec<u8>,
+        options: FileOptions,
+    ) -> Result<Self> {
+        Self::create_with_rate_limiter(fs, path, data, options, None)
+    }
+
+    // create the file with the writes throttled by `rate_limiter`
+    pub fn create_with_rate_limiter(
+        fs: &dyn FileSystem,
+        path: &Path,
+        data: Vec<u8>,
+        options: FileOptions,
+        rate_limiter: Option<(&RateLimiter, IoPriority)>,
+    ) -> Result<Self> {
+        let mut file = fs.create(path, options)?;
+        match rate_limiter {
+            Some((rate_limiter, priority)) => {
+                rate_limiter.append(file.as_mut(), &data, priority)?
+            }
+            None => file.append(&data)?,
+        }
+        file.sync()?;
+        Self::open(fs, path, options)
+    }
+
+    pub fn open(fs: &dyn FileSystem, path: &Path, options: FileOptions) -> Result<Self> {
+        let file = fs.open(path, options)?;
+        let size = file.size();
+        Ok(FileObject { file, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::FileObject;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+    use crate::fs::MemFileSystem;
+    use crate::fs::PosixFileSystem;
+
+    fn test_read_file_object(fs: &dyn FileSystem, dir: &std::path::Path) -> Result<()> {
+        let data: Vec<u8> = (0..=255).collect();
+        let path = dir.join("1.sst");
+        let file = FileObject::create(fs, &path, data.clone(), FileOptions::default())?;
+        assert_eq!(file.size(), data.len());
+        assert_eq!(file.read(10, 20)?.as_ref(), &data[10..30]);
+        assert_eq!(file.read(0, 256)?.as_ref(), &data[..]);
+        assert!(file.read(250, 10).is_err());
+
+        let file = FileObject::open(fs, &path, FileOptions::default())?;
+        assert_eq!(file.read(100, 1)?.as_ref(), &data[100..101]);
+        let result = file.read_batch(&[(0, 10), (200, 56)])?;
+        assert_eq!(result[0].as_ref(), &data[..10]);
+        assert_eq!(result[1].as_ref(), &data[200..]);
+        assert!(file.read_batch(&[(0, 10), (255, 2)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_posix_file_object() -> Result<()> {
+        let dir = tempdir()?;
+        test_read_file_object(&PosixFileSystem, dir.path())
     }
 
-    pub fn open(path: &Path) -> Result<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
-        Ok(FileObject {
-            file: Some(file),
-            size: size as usize,
-        })
+    #[test]
+    fn test_mem_file_object() -> Result<()> {
+        let fs = MemFileSystem::new();
+        fs.create_dir_all(std::path::Path::new("/db"))?;
+        test_read_file_object(&fs, std::path::Path::new("/db"))
     }
 }
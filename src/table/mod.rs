@@ -12,16 +12,34 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod block_cache;
 mod block_meta;
 mod file;
+mod sst_file_writer;
 #[allow(clippy::module_inception)]
 mod table;
 mod table_builder;
+mod table_cache;
+mod table_iterator;
+mod table_properties;
 
+pub use block_cache::BlockCache;
+pub use block_cache::CachedBlock;
 pub(crate) use block_meta::BlockMeta;
 pub(crate) use block_meta::BlockMetaVec;
-pub(crate) use file::FileObject;
+pub use file::FileObject;
+pub use sst_file_writer::ExternalSstFileInfo;
+pub use sst_file_writer::SstFileWriter;
+pub use table::IndexType;
 pub use table::SsTable;
 pub use table::SsTableId;
+pub use table::SsTableInfo;
 pub use table::SsTableMeta;
 pub use table_builder::SsTableBuilder;
+pub use table_cache::TableCache;
+pub use table_iterator::SsTableIterator;
+pub use table_properties::CompressionType;
+pub use table_properties::FilterType;
+pub use table_properties::TableProperties;
+pub use table_properties::TablePropertiesCollector;
+pub use table_properties::TablePropertiesCollectorFactory;
@@ -0,0 +1,134 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use anyhow::bail;
+use bytes::Bytes;
+
+use super::SsTableBuilder;
+use crate::base::KeySlice;
+use crate::base::VERSION_DEFAULT;
+use crate::engine::LsmOptions;
+
+// Information of a finished external sstable
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ExternalSstFileInfo {
+    pub path: PathBuf,
+    pub first_key: Bytes,
+    pub last_key: Bytes,
+    pub num_entries: u64,
+    pub file_size: u64,
+}
+
+// Writes a standalone sstable which can be ingested by `LsmEngine::ingest_external_files`.
+// Keys MUST be added in strictly increasing order, they are saved with the default version
+// and get a global version when the file is ingested.
+pub struct SstFileWriter {
+    builder: SsTableBuilder,
+    path: PathBuf,
+
+    first_key: Bytes,
+    last_key: Bytes,
+    num_entries: u64,
+}
+
+impl SstFileWriter {
+    pub fn create(path: impl AsRef<Path>, options: &LsmOptions) -> Result<Self> {
+        Ok(Self {
+            builder: SsTableBuilder::create_with_options(options)?,
+            path: path.as_ref().to_path_buf(),
+            first_key: Bytes::new(),
+            last_key: Bytes::new(),
+            num_entries: 0,
+        })
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            bail!("key of external sstable MUST not be empty");
+        }
+        if self.num_entries > 0 && key <= self.last_key.as_ref() {
+            bail!(
+                "keys of external sstable MUST be added in strictly increasing order, {:?} after {:?}",
+                key,
+                self.last_key
+            );
+        }
+
+        self.builder
+            .add(KeySlice::from_slice(key, VERSION_DEFAULT), value)?;
+        if self.num_entries == 0 {
+            self.first_key = Bytes::copy_from_slice(key);
+        }
+        self.last_key = Bytes::copy_from_slice(key);
+        self.num_entries += 1;
+        Ok(())
+    }
+
+    // an empty value is a deletion
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.put(key, &[])
+    }
+
+    pub fn finish(self) -> Result<ExternalSstFileInfo> {
+        if self.num_entries == 0 {
+            bail!("cannot finish an empty external sstable {:?}", self.path);
+        }
+        let table = self.builder.build(0, None, &self.path)?;
+        Ok(ExternalSstFileInfo {
+            path: self.path,
+            first_key: self.first_key,
+            last_key: self.last_key,
+            num_entries: self.num_entries,
+            file_size: table.table_size() as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::SstFileWriter;
+    use crate::engine::LsmOptions;
+
+    #[test]
+    fn test_write_external_sstable() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("external.sst");
+        let mut writer = SstFileWriter::create(&path, &LsmOptions::default())?;
+        writer.put(b"a", b"1")?;
+        writer.delete(b"b")?;
+        writer.put(b"c", b"3")?;
+        // keys must be strictly increasing
+        assert!(writer.put(b"c", b"4").is_err());
+        assert!(writer.put(b"b", b"2").is_err());
+
+        let info = writer.finish()?;
+        assert_eq!(info.path, path);
+        assert_eq!(info.first_key.as_ref(), b"a");
+        assert_eq!(info.last_key.as_ref(), b"c");
+        assert_eq!(info.num_entries, 3);
+        assert_eq!(info.file_size, std::fs::metadata(&path)?.len());
+
+        let writer = SstFileWriter::create(dir.path().join("empty.sst"), &LsmOptions::default())?;
+        assert!(writer.finish().is_err());
+
+        Ok(())
+    }
+}
@@ -12,57 +12,568 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::File;
-use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
 
 use anyhow::Result;
+use anyhow::bail;
+use bytes::Buf;
+use bytes::Bytes;
+use tinysearch_cuckoofilter::CuckooFilter;
+use tinysearch_cuckoofilter::ExportedCuckooFilter;
 
+use super::BlockCache;
 use super::BlockMetaVec;
+use super::CachedBlock;
+use super::FileObject;
+use super::TableProperties;
+use crate::base::KeySlice;
 use crate::base::KeyVec;
 use crate::base::Version;
+use crate::block::Block;
 
 pub type SsTableId = u64;
 
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U8: usize = std::mem::size_of::<u8>();
+
+// one seek costs about as much as compacting this many bytes, see `SsTableInfo::allowed_seeks`
+const SEEK_COST_BYTES: usize = 16 << 10;
+const MIN_ALLOWED_SEEKS: i64 = 100;
+
+// How the block metas of a sstable are stored:
+// - Flat: all the block metas are saved in one block meta vector,
+//   which is loaded in memory when the table is opened.
+// - Partitioned: the block metas are split into index partitions, the block meta vector
+//   saved in the table is a top-level index whose entries point to the partitions.
+//   Only the top-level index stays in memory, partitions are loaded on demand
+//   through the block cache.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexType {
+    Flat = 0,
+    Partitioned = 1,
+}
+
+impl IndexType {
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(IndexType::Flat),
+            1 => Ok(IndexType::Partitioned),
+            _ => bail!("unknown sstable index type {}", value),
+        }
+    }
+}
+
 pub struct SsTableMeta {
     pub id: SsTableId,
 
     pub first_key: KeyVec,
     pub last_key: KeyVec,
 
-    pub block_meta_vec: BlockMetaVec,
+    // block metas if index type is `Flat`, else the top-level index of partitions
+    pub block_meta_vec: Arc<BlockMetaVec>,
     pub block_meta_offset: usize,
 
+    pub index_type: IndexType,
+
+    // versions of all the keys in the table are in [min_version, max_version]
+    pub min_version: Version,
+    pub max_version: Version,
+
+    pub properties: TableProperties,
+}
+
+// Summary of a sstable kept in the engine state, the table itself is
+// opened lazily through the table cache when it is read
+#[derive(Clone, Debug)]
+pub struct SsTableInfo {
+    pub id: SsTableId,
+
+    pub first_key: KeyVec,
+    pub last_key: KeyVec,
+
+    pub min_version: Version,
     pub max_version: Version,
+
+    // see `SsTable::global_version`
+    pub global_version: Option<Version>,
+
+    pub table_size: usize,
+
+    pub properties: TableProperties,
+
+    // number of lookups searching the table without finding the key, before
+    // it is compacted into the next level to cut the lookups. shared by the
+    // clones of the info.
+    pub allowed_seeks: Arc<AtomicI64>,
+}
+
+impl SsTableInfo {
+    pub fn id(&self) -> SsTableId {
+        self.id
+    }
+
+    pub fn first_key(&self) -> &KeyVec {
+        &self.first_key
+    }
+
+    pub fn last_key(&self) -> &KeyVec {
+        &self.last_key
+    }
+
+    pub fn global_version(&self) -> Option<Version> {
+        self.global_version
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.table_size
+    }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    pub fn allowed_seeks(&self) -> i64 {
+        self.allowed_seeks.load(Ordering::Relaxed)
+    }
+
+    // a lookup searched the table without finding the key
+    pub fn record_seek(&self) {
+        self.allowed_seeks.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn reset_allowed_seeks(&self) {
+        self.allowed_seeks
+            .store(initial_allowed_seeks(self.table_size), Ordering::Relaxed);
+    }
+
+    // return true if `key` is in the key range of the table
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.first_key.key_ref() <= key && key <= self.last_key.key_ref()
+    }
+
+    // return true if the key range of the table overlaps [first, last]
+    pub fn overlaps_key_range(&self, first: &[u8], last: &[u8]) -> bool {
+        self.first_key.key_ref() <= last && first <= self.last_key.key_ref()
+    }
+
+    // return true if the table may contain keys with version in [min_version, max_version]
+    pub fn overlaps_versions(&self, min_version: Version, max_version: Version) -> bool {
+        self.min_version <= max_version && self.max_version >= min_version
+    }
 }
 
-struct SsTableFile {}
-
-impl SsTableFile {
-    // pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-    // std::fs::write(path, &data)
-    // .map_err(Error::io_error(format!("write to SsTable file {:?}", path)))?;
-    // let file = File::options()
-    // .read(true)
-    // .write(false)
-    // .open(path)
-    // .map_err(Error::io_error(format!("open SsTable file {:?}", path)))?;
-    // Ok(SsTableFile {
-    // file: Some(file),
-    // size: data.len(),
-    // })
-    // }
+fn initial_allowed_seeks(table_size: usize) -> i64 {
+    std::cmp::max((table_size / SEEK_COST_BYTES) as i64, MIN_ALLOWED_SEEKS)
 }
 
+// Sstable format:
+// data blocks: [encoded block + block checksum(u32)] ...
+// index partitions(only if index type is `Partitioned`): [encoded block meta vector] ...
+// block meta vector: encoded block meta vector
+// block meta offset(u32) + index type(u8)
+// filter: bincode of cuckoo filter
+// properties: encoded table properties
+// filter offset(u32) + properties offset(u32)
 pub struct SsTable {
     pub meta: SsTableMeta,
-    file: SsTableFile,
+    file: FileObject,
+    filter: CuckooFilter<farmhash::FarmHasher>,
+    block_cache: Option<Arc<BlockCache>>,
+
+    // version of all the keys in an ingested table, overrides the versions saved in blocks
+    global_version: Option<Version>,
 }
 
 impl SsTable {
-    // pub fn create(meta: SsTableMeta, path: &Path, data: Vec<u8>) -> Result<Self> {
-    // Ok(Self {
-    // meta,
-    // file: SsTableFile::create(path, data)?,
-    // })
-    // }
+    pub fn create(
+        meta: SsTableMeta,
+        filter: CuckooFilter<farmhash::FarmHasher>,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+    ) -> Result<Self> {
+        Ok(Self {
+            meta,
+            file,
+            filter,
+            block_cache,
+            global_version: None,
+        })
+    }
+
+    pub fn open(
+        id: SsTableId,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+    ) -> Result<Self> {
+        let len = file.size();
+        if len < SIZEOF_U32 * 2 {
+            bail!("sstable {} is too small", id);
+        }
+
+        // read filter and properties offset
+        let properties_end = len - SIZEOF_U32 * 2;
+        let footer = file.read(properties_end as u64, (SIZEOF_U32 * 2) as u64)?;
+        let mut footer = &footer[..];
+        let filter_offset = footer.get_u32() as usize;
+        let properties_offset = footer.get_u32() as usize;
+        if filter_offset < SIZEOF_U32 + SIZEOF_U8
+            || filter_offset > properties_offset
+            || properties_offset > properties_end
+        {
+            bail!(
+                "sstable {} has invalid filter offset {} or properties offset {}",
+                id,
+                filter_offset,
+                properties_offset
+            );
+        }
+
+        // read properties
+        let properties_data = file.read(
+            properties_offset as u64,
+            (properties_end - properties_offset) as u64,
+        )?;
+        let properties = TableProperties::decode(&properties_data)?;
+
+        // read filter
+        let filter_data = file.read(
+            filter_offset as u64,
+            (properties_offset - filter_offset) as u64,
+        )?;
+        let export_filter: ExportedCuckooFilter = bincode::deserialize(&filter_data)?;
+        let filter = CuckooFilter::<farmhash::FarmHasher>::from(export_filter);
+
+        // read block meta offset and index type
+        let index_footer_offset = filter_offset - SIZEOF_U32 - SIZEOF_U8;
+        let index_footer =
+            file.read(index_footer_offset as u64, (SIZEOF_U32 + SIZEOF_U8) as u64)?;
+        let mut index_footer = &index_footer[..];
+        let block_meta_offset = index_footer.get_u32() as usize;
+        let index_type = IndexType::from_u8(index_footer.get_u8())?;
+        if block_meta_offset > index_footer_offset {
+            bail!(
+                "sstable {} has an invalid block meta offset {}",
+                id,
+                block_meta_offset
+            );
+        }
+
+        // read block meta vector
+        let block_meta_data = file.read(
+            block_meta_offset as u64,
+            (index_footer_offset - block_meta_offset) as u64,
+        )?;
+        let (max_version, block_meta_vec) = BlockMetaVec::decode(&block_meta_data)?;
+        let (first_key, last_key) = match (block_meta_vec.first(), block_meta_vec.last()) {
+            (Some(first), Some(last)) => (
+                first.first_key.to_key_slice().to_key_vec(),
+                last.last_key.to_key_slice().to_key_vec(),
+            ),
+            _ => bail!("sstable {} has no block", id),
+        };
+        let min_version = block_meta_vec.min_version();
+
+        Ok(Self {
+            meta: SsTableMeta {
+                id,
+                first_key,
+                last_key,
+                block_meta_vec: Arc::new(block_meta_vec),
+                block_meta_offset,
+                index_type,
+                min_version,
+                max_version,
+                properties,
+            },
+            file,
+            filter,
+            block_cache,
+            global_version: None,
+        })
+    }
+
+    pub fn id(&self) -> SsTableId {
+        self.meta.id
+    }
+
+    pub fn info(&self) -> SsTableInfo {
+        SsTableInfo {
+            id: self.meta.id,
+            first_key: self.meta.first_key.clone(),
+            last_key: self.meta.last_key.clone(),
+            min_version: self.meta.min_version,
+            max_version: self.meta.max_version,
+            global_version: self.global_version,
+            table_size: self.table_size(),
+            properties: self.meta.properties.clone(),
+            allowed_seeks: Arc::new(AtomicI64::new(initial_allowed_seeks(self.table_size()))),
+        }
+    }
+
+    pub fn first_key(&self) -> &KeyVec {
+        &self.meta.first_key
+    }
+
+    pub fn last_key(&self) -> &KeyVec {
+        &self.meta.last_key
+    }
+
+    pub fn global_version(&self) -> Option<Version> {
+        self.global_version
+    }
+
+    pub fn set_global_version(&mut self, version: Version) {
+        self.global_version = Some(version);
+        self.meta.min_version = version;
+        self.meta.max_version = version;
+    }
+
+    // return true if `key` is in the key range of the table
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.meta.first_key.key_ref() <= key && key <= self.meta.last_key.key_ref()
+    }
+
+    // return true if the key range of the table overlaps [first, last]
+    pub fn overlaps_key_range(&self, first: &[u8], last: &[u8]) -> bool {
+        self.meta.first_key.key_ref() <= last && first <= self.meta.last_key.key_ref()
+    }
+
+    pub fn min_version(&self) -> Version {
+        self.meta.min_version
+    }
+
+    pub fn max_version(&self) -> Version {
+        self.meta.max_version
+    }
+
+    // return true if the table may contain keys with version in [min_version, max_version]
+    pub fn overlaps_versions(&self, min_version: Version, max_version: Version) -> bool {
+        self.meta.min_version <= max_version && self.meta.max_version >= min_version
+    }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.meta.properties
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.file.size()
+    }
+
+    // return false if the table must not contain `key`
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter.contains(&farmhash::fingerprint32(key))
+    }
+
+    // first keys of the blocks, or of the index partitions if the index is partitioned.
+    // read from the index in memory, they split the table into parts of similar size
+    pub fn index_samples(&self) -> Vec<&[u8]> {
+        self.meta
+            .block_meta_vec
+            .iter()
+            .map(|meta| meta.first_key.key_ref())
+            .collect()
+    }
+
+    pub fn num_index_partitions(&self) -> usize {
+        match self.meta.index_type {
+            IndexType::Flat => 1,
+            IndexType::Partitioned => self.meta.block_meta_vec.len(),
+        }
+    }
+
+    // return block metas of the index partition
+    pub fn read_index_partition(&self, partition_idx: usize) -> Result<Arc<BlockMetaVec>> {
+        if self.meta.index_type == IndexType::Flat {
+            assert_eq!(partition_idx, 0);
+            return Ok(self.meta.block_meta_vec.clone());
+        }
+
+        let top_level = &self.meta.block_meta_vec;
+        let offset = top_level.get(partition_idx).offset;
+        // partitions are saved one by one, followed by the top-level index
+        let end = if partition_idx + 1 < top_level.len() {
+            top_level.get(partition_idx + 1).offset
+        } else {
+            self.meta.block_meta_offset
+        };
+
+        let load = || -> Result<CachedBlock> {
+            let data = self.file.read(offset as u64, (end - offset) as u64)?;
+            let (_, partition) = BlockMetaVec::decode(&data)?;
+            Ok(CachedBlock::IndexPartition(Arc::new(partition)))
+        };
+        let cached = match &self.block_cache {
+            Some(block_cache) => block_cache.get_or_load(self.id(), offset, load)?,
+            None => load()?,
+        };
+        match cached {
+            CachedBlock::IndexPartition(partition) => Ok(partition),
+            CachedBlock::Data(_) => {
+                bail!("sstable {} offset {} is not an index", self.id(), offset)
+            }
+        }
+    }
+
+    pub fn read_block(&self, partition_idx: usize, block_idx: usize) -> Result<Arc<Block>> {
+        let partition = self.read_index_partition(partition_idx)?;
+        self.read_block_in_partition(&partition, partition_idx, block_idx)
+    }
+
+    // read block with the already loaded index partition
+    pub fn read_block_in_partition(
+        &self,
+        partition: &BlockMetaVec,
+        partition_idx: usize,
+        block_idx: usize,
+    ) -> Result<Arc<Block>> {
+        let (offset, end) = self.block_range(partition, partition_idx, block_idx)?;
+        let load = || -> Result<CachedBlock> {
+            let data = self.file.read(offset as u64, (end - offset) as u64)?;
+            Ok(CachedBlock::Data(self.decode_block(offset, data)?))
+        };
+        let cached = match &self.block_cache {
+            Some(block_cache) => block_cache.get_or_load(self.id(), offset, load)?,
+            None => load()?,
+        };
+        self.cached_data_block(offset, cached)
+    }
+
+    // read the (partition index, block index) blocks, blocks missing in the block cache
+    // are read with one batched read and inserted into the cache
+    pub fn read_blocks(&self, blocks: &[(usize, usize)]) -> Result<Vec<Arc<Block>>> {
+        let mut result = Vec::with_capacity(blocks.len());
+        let mut missing = Vec::new();
+        let mut ranges = Vec::new();
+        for (partition_idx, block_idx) in blocks {
+            let partition = self.read_index_partition(*partition_idx)?;
+            let (offset, end) = self.block_range(&partition, *partition_idx, *block_idx)?;
+            let cached = match &self.block_cache {
+                Some(block_cache) => block_cache.get(self.id(), offset),
+                None => None,
+            };
+            match cached {
+                Some(cached) => result.push(Some(self.cached_data_block(offset, cached)?)),
+                None => {
+                    result.push(None);
+                    missing.push((result.len() - 1, offset));
+                    ranges.push((offset as u64, (end - offset) as u64));
+                }
+            }
+        }
+
+        let datas = self.file.read_batch(&ranges)?;
+        for ((idx, offset), data) in missing.into_iter().zip(datas) {
+            let block = self.decode_block(offset, data)?;
+            if let Some(block_cache) = &self.block_cache {
+                block_cache.insert(self.id(), offset, CachedBlock::Data(block.clone()));
+            }
+            result[idx] = Some(block);
+        }
+        // safe to unwrap, all the missing blocks are read
+        Ok(result.into_iter().map(|block| block.unwrap()).collect())
+    }
+
+    // return [start, end) offset of the block in file
+    fn block_range(
+        &self,
+        partition: &BlockMetaVec,
+        partition_idx: usize,
+        block_idx: usize,
+    ) -> Result<(usize, usize)> {
+        let offset = partition.get(block_idx).offset;
+        let end = if block_idx + 1 < partition.len() {
+            partition.get(block_idx + 1).offset
+        } else if partition_idx + 1 < self.num_index_partitions() {
+            self.read_index_partition(partition_idx + 1)?.get(0).offset
+        } else {
+            self.data_end_offset()
+        };
+        Ok((offset, end))
+    }
+
+    // verify the checksum and decode the block data read from file
+    fn decode_block(&self, offset: usize, data: Bytes) -> Result<Arc<Block>> {
+        if data.len() < SIZEOF_U32 {
+            bail!(
+                "sstable {} block at {} is too short: {} bytes",
+                self.id(),
+                offset,
+                data.len()
+            );
+        }
+        let block_len = data.len() - SIZEOF_U32;
+        if (&data[block_len..]).get_u32() != crc32fast::hash(&data[..block_len]) {
+            bail!(
+                "sstable {} block at {} checksum mismatched",
+                self.id(),
+                offset
+            );
+        }
+        Ok(Arc::new(Block::decode_from_bytes(data.slice(..block_len))))
+    }
+
+    fn cached_data_block(&self, offset: usize, cached: CachedBlock) -> Result<Arc<Block>> {
+        match cached {
+            CachedBlock::Data(block) => Ok(block),
+            CachedBlock::IndexPartition(_) => {
+                bail!(
+                    "sstable {} offset {} is not a data block",
+                    self.id(),
+                    offset
+                )
+            }
+        }
+    }
+
+    // return true if the index partition may contain keys with version in [min_version, max_version]
+    pub fn partition_overlaps_versions(
+        &self,
+        partition_idx: usize,
+        min_version: Version,
+        max_version: Version,
+    ) -> bool {
+        if self.global_version.is_some() {
+            return self.overlaps_versions(min_version, max_version);
+        }
+        match self.meta.index_type {
+            IndexType::Flat => self.overlaps_versions(min_version, max_version),
+            IndexType::Partitioned => self
+                .meta
+                .block_meta_vec
+                .get(partition_idx)
+                .overlaps_versions(min_version, max_version),
+        }
+    }
+
+    // return (partition index, block index) of the first block whose last key >= `key`,
+    // return None if all the keys in the table are less than `key`
+    pub fn find_block(&self, key: KeySlice) -> Result<Option<(usize, usize)>> {
+        let partition_idx = match self.meta.index_type {
+            IndexType::Flat => 0,
+            IndexType::Partitioned => {
+                let idx = self.meta.block_meta_vec.search(key);
+                if idx >= self.meta.block_meta_vec.len() {
+                    return Ok(None);
+                }
+                idx
+            }
+        };
+
+        let partition = self.read_index_partition(partition_idx)?;
+        let block_idx = partition.search(key);
+        if block_idx >= partition.len() {
+            return Ok(None);
+        }
+        Ok(Some((partition_idx, block_idx)))
+    }
+
+    // data blocks end at the first index partition, or the block meta vector
+    fn data_end_offset(&self) -> usize {
+        match self.meta.index_type {
+            IndexType::Flat => self.meta.block_meta_offset,
+            IndexType::Partitioned => self.meta.block_meta_vec.get(0).offset,
+        }
+    }
 }
@@ -13,25 +13,39 @@
 // limitations under the License.
 
 use std::path::Path;
+use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::BufMut;
 use tinysearch_cuckoofilter::CuckooFilter;
 
+use super::BlockCache;
 use super::BlockMetaVec;
+use super::FileObject;
+use super::IndexType;
 use super::SsTableId;
 use super::SsTableMeta;
+use super::TableProperties;
+use super::TablePropertiesCollector;
 use crate::base::KeySlice;
 use crate::base::KeyVec;
 use crate::base::VERSION_DEFAULT;
 use crate::base::Version;
 use crate::block::BlockBuilder;
+use crate::engine::LsmOptions;
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::IoPriority;
+use crate::fs::PosixFileSystem;
+use crate::fs::RateLimiter;
 use crate::table::BlockMeta;
 use crate::table::SsTable;
 
 pub struct SsTableBuilder {
     block_builder: BlockBuilder,
-    filter: CuckooFilter<farmhash::FarmHasher>,
+    // hashes of the user keys, the filter is built from them when the table is built
+    // so that it can be sized by the number of distinct keys
+    key_hashes: Vec<u32>,
 
     first_key: KeyVec,
     last_key: KeyVec,
@@ -40,39 +54,121 @@ pub struct SsTableBuilder {
 
     block_meta_vec: BlockMetaVec,
 
+    // finished index partitions, only used if `index_partition_size` > 0
+    index_partitions: Vec<BlockMetaVec>,
+
     max_version: Version,
 
+    // min and max version of the current block
+    block_min_version: Version,
+    block_max_version: Version,
+
+    properties: TableProperties,
+    properties_collectors: Vec<Box<dyn TablePropertiesCollector>>,
+
     block_size: usize,
+
+    // cut a new index partition when block metas of the partition exceed this size,
+    // 0 means the index is not partitioned
+    index_partition_size: usize,
+
+    // options of the built sstable file
+    file_options: FileOptions,
+    fs: Arc<dyn FileSystem>,
+
+    // throttles writing the sstable file
+    rate_limiter: Option<Arc<RateLimiter>>,
+    io_priority: IoPriority,
 }
 
 impl SsTableBuilder {
     pub fn create(block_size: usize) -> Result<Self> {
         Ok(SsTableBuilder {
             block_builder: BlockBuilder::new(block_size),
-            filter: CuckooFilter::<farmhash::FarmHasher>::with_capacity(10240),
+            key_hashes: Vec::new(),
 
             first_key: KeyVec::new(),
             last_key: KeyVec::new(),
 
             data: Vec::new(),
             block_meta_vec: BlockMetaVec::new(),
+            index_partitions: Vec::new(),
 
             max_version: VERSION_DEFAULT,
+
+            block_min_version: Version::MAX,
+            block_max_version: VERSION_DEFAULT,
+
+            properties: TableProperties::new(),
+            properties_collectors: Vec::new(),
+
             block_size,
+            index_partition_size: 0,
+            file_options: FileOptions::default(),
+            fs: Arc::new(PosixFileSystem),
+            rate_limiter: None,
+            io_priority: IoPriority::Low,
         })
     }
 
+    // create a builder configured by the engine options
+    pub fn create_with_options(options: &LsmOptions) -> Result<Self> {
+        let mut builder = Self::create(options.block_size)?;
+        builder.set_index_partition_size(options.index_partition_size);
+        builder.set_file_options(options.file_options());
+        builder.set_file_system(options.fs.clone());
+        builder.set_rate_limiter(options.rate_limiter.clone());
+        for factory in &options.table_properties_collector_factories {
+            builder.add_properties_collector(factory.create());
+        }
+        Ok(builder)
+    }
+
+    pub fn set_index_partition_size(&mut self, index_partition_size: usize) {
+        self.index_partition_size = index_partition_size;
+    }
+
+    pub fn set_file_options(&mut self, file_options: FileOptions) {
+        self.file_options = file_options;
+    }
+
+    pub fn set_file_system(&mut self, fs: Arc<dyn FileSystem>) {
+        self.fs = fs;
+    }
+
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<Arc<RateLimiter>>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    // priority of the writes of the sstable file, low by default
+    pub fn set_io_priority(&mut self, io_priority: IoPriority) {
+        self.io_priority = io_priority;
+    }
+
+    pub fn add_properties_collector(&mut self, collector: Box<dyn TablePropertiesCollector>) {
+        self.properties_collectors.push(collector);
+    }
+
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> Result<()> {
         if self.first_key.is_empty() {
             self.first_key = KeyVec::from_key_slice(&key);
         }
         self.max_version = std::cmp::max(self.max_version, key.version());
+        self.properties.add(key, value);
+        for collector in &mut self.properties_collectors {
+            collector.add(key, value);
+        }
 
-        self.filter.add(&farmhash::fingerprint32(key.key_ref()))?;
+        // versions of one user key are adjacent, only add the key once
+        let hash = farmhash::fingerprint32(key.key_ref());
+        if self.key_hashes.last() != Some(&hash) {
+            self.key_hashes.push(hash);
+        }
 
         // if the block is not full, `add` return true
         if self.block_builder.add(key, value) {
             self.last_key = KeyVec::from_key_slice(&key);
+            self.update_block_version(key.version());
             return Ok(());
         }
 
@@ -84,10 +180,25 @@ impl SsTableBuilder {
         assert!(self.block_builder.add(key, value));
         self.first_key = KeyVec::from_key_slice(&key);
         self.last_key = KeyVec::from_key_slice(&key);
+        self.update_block_version(key.version());
 
         Ok(())
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.properties.num_entries == 0
+    }
+
+    // size of the data blocks written so far
+    pub fn estimated_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn update_block_version(&mut self, version: Version) {
+        self.block_min_version = std::cmp::min(self.block_min_version, version);
+        self.block_max_version = std::cmp::max(self.block_max_version, version);
+    }
+
     // save [encoded block + block checksum(u32)] into data buffer
     fn finalize(&mut self) {
         let block_builder =
@@ -98,41 +209,415 @@ impl SsTableBuilder {
             offset: self.data.len(),
             first_key: self.first_key.to_key_bytes(),
             last_key: self.last_key.to_key_bytes(),
+            min_version: self.block_min_version,
+            max_version: self.block_max_version,
         });
+        self.block_min_version = Version::MAX;
+        self.block_max_version = VERSION_DEFAULT;
         let checksum = crc32fast::hash(&encoded_block);
         self.data.extend(encoded_block);
         self.data.put_u32(checksum);
+
+        if self.index_partition_size > 0
+            && self.block_meta_vec.estimated_size() >= self.index_partition_size
+        {
+            self.index_partitions.push(std::mem::replace(
+                &mut self.block_meta_vec,
+                BlockMetaVec::new(),
+            ));
+        }
     }
 
-    pub fn build(mut self, id: SsTableId, path: impl AsRef<Path>) -> Result<SsTable> {
+    pub fn build(
+        mut self,
+        id: SsTableId,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
         self.finalize();
         let mut data = self.data;
 
+        // save index partitions, and make the top-level index point to them
+        let (index_type, block_meta_vec) = if self.index_partition_size > 0 {
+            if !self.block_meta_vec.is_empty() {
+                self.index_partitions.push(self.block_meta_vec);
+            }
+            let mut top_level = BlockMetaVec::new();
+            for partition in &self.index_partitions {
+                // safe to unwrap, partitions are never empty
+                top_level.push(BlockMeta {
+                    offset: data.len(),
+                    first_key: partition.first().unwrap().first_key.clone(),
+                    last_key: partition.last().unwrap().last_key.clone(),
+                    min_version: partition.min_version(),
+                    max_version: partition.max_version(),
+                });
+                partition.encode(self.max_version, &mut data);
+            }
+            (IndexType::Partitioned, top_level)
+        } else {
+            (IndexType::Flat, self.block_meta_vec)
+        };
+
         // save block meta vectors
         let block_meta_offset = data.len();
-        self.block_meta_vec.encode(self.max_version, &mut data);
+        block_meta_vec.encode(self.max_version, &mut data);
         data.put_u32(block_meta_offset as u32);
+        data.put_u8(index_type as u8);
 
         // save filter data
-        let export_filter = self.filter.export();
+        let filter = Self::build_filter(self.key_hashes)?;
+        let export_filter = filter.export();
         let filter_data = bincode::serialize(&export_filter)?;
-        data.extend(&filter_data);
         let filter_offset = data.len();
+        data.extend(&filter_data);
+
+        // save properties
+        let mut properties = self.properties;
+        properties.set_creation_time_now();
+        for collector in &mut self.properties_collectors {
+            properties.user_collected.append(&mut collector.finish());
+        }
+        let properties_offset = data.len();
+        properties.encode(&mut data)?;
+
         data.put_u32(filter_offset as u32);
+        data.put_u32(properties_offset as u32);
 
-        // create sstable meta
+        // create sstable meta, safe to unwrap since there is at least one block
+        let min_version = block_meta_vec.min_version();
         let table_meta = SsTableMeta {
             id,
-            first_key: self.first_key.clone(),
-            last_key: self.last_key.clone(),
-            block_meta_vec: self.block_meta_vec.clone(),
+            first_key: block_meta_vec
+                .first()
+                .unwrap()
+                .first_key
+                .to_key_slice()
+                .to_key_vec(),
+            last_key: block_meta_vec
+                .last()
+                .unwrap()
+                .last_key
+                .to_key_slice()
+                .to_key_vec(),
+            block_meta_vec: Arc::new(block_meta_vec),
             block_meta_offset,
+            index_type,
+            min_version,
             max_version: self.max_version,
+            properties,
         };
 
-        SsTable::create(table_meta, path.as_ref(), data)
+        let rate_limiter = self
+            .rate_limiter
+            .as_deref()
+            .map(|rate_limiter| (rate_limiter, self.io_priority));
+        let file = FileObject::create_with_rate_limiter(
+            self.fs.as_ref(),
+            path.as_ref(),
+            data,
+            self.file_options,
+            rate_limiter,
+        )?;
+        SsTable::create(table_meta, filter, block_cache, file)
+    }
+
+    // build a cuckoo filter of the key hashes, the filter is sized twice the number of
+    // distinct hashes, and is rebuilt with doubled capacity in the unlikely case it is full
+    fn build_filter(mut key_hashes: Vec<u32>) -> Result<CuckooFilter<farmhash::FarmHasher>> {
+        key_hashes.sort_unstable();
+        key_hashes.dedup();
+        let mut capacity = std::cmp::max(key_hashes.len() * 2, 1);
+        loop {
+            let mut filter = CuckooFilter::<farmhash::FarmHasher>::with_capacity(capacity);
+            if key_hashes.iter().all(|hash| filter.add(hash).is_ok()) {
+                return Ok(filter);
+            }
+            capacity *= 2;
+        }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use super::SsTableBuilder;
+    use crate::base::KeyBytes;
+    use crate::base::KeySlice;
+    use crate::fs::FaultInjectionFileSystem;
+    use crate::fs::FileOptions;
+    use crate::fs::FileSystem;
+    use crate::fs::MemFileSystem;
+    use crate::fs::PosixFileSystem;
+    use crate::fs::ReadFault;
+    use crate::fs::run_crash_test;
+    use crate::table::BlockCache;
+    use crate::table::FileObject;
+    use crate::table::IndexType;
+    use crate::table::SsTable;
+    use crate::table::TablePropertiesCollector;
+
+    fn key_of(i: usize) -> KeyBytes {
+        KeyBytes::new(Bytes::from(format!("key_{:05}", i)), i as u64)
+    }
+
+    fn build_table(index_partition_size: usize, path: &std::path::Path) -> Result<SsTable> {
+        let mut builder = SsTableBuilder::create(128)?;
+        builder.set_index_partition_size(index_partition_size);
+        for i in 0..1000 {
+            let value = format!("value_{}", i);
+            builder.add(key_of(i).to_key_slice(), value.as_bytes())?;
+        }
+        builder.build(1, None, path)
+    }
+
+    fn check_table(table: &SsTable) -> Result<usize> {
+        assert_eq!(table.first_key().to_key_slice(), key_of(0).to_key_slice());
+        assert_eq!(table.last_key().to_key_slice(), key_of(999).to_key_slice());
+        assert_eq!(table.max_version(), 999);
+
+        // every key can be found in the block that covers it
+        for i in 0..1000 {
+            let key = key_of(i);
+            assert!(table.may_contain(key.key_ref()));
+            let (partition_idx, block_idx) = table.find_block(key.to_key_slice())?.unwrap();
+            let meta = table
+                .read_index_partition(partition_idx)?
+                .get(block_idx)
+                .clone();
+            assert!(meta.first_key.to_key_slice() <= key.to_key_slice());
+            assert!(meta.last_key.to_key_slice() >= key.to_key_slice());
+        }
+        assert!(table.find_block(key_of(1000).to_key_slice())?.is_none());
+
+        // all the blocks can be read, including the last block of each partition
+        let mut num_blocks = 0;
+        for partition_idx in 0..table.num_index_partitions() {
+            let partition = table.read_index_partition(partition_idx)?;
+            for block_idx in 0..partition.len() {
+                table.read_block(partition_idx, block_idx)?;
+                num_blocks += 1;
+            }
+        }
+        Ok(num_blocks)
+    }
+
+    #[test]
+    fn test_build_and_open_flat_index() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("1.sst");
+        let table = build_table(0, &path)?;
+        assert_eq!(table.meta.index_type, IndexType::Flat);
+        assert_eq!(table.num_index_partitions(), 1);
+        let num_blocks = check_table(&table)?;
+
+        let table = SsTable::open(
+            1,
+            None,
+            FileObject::open(&PosixFileSystem, &path, FileOptions::default())?,
+        )?;
+        assert_eq!(table.meta.index_type, IndexType::Flat);
+        assert_eq!(check_table(&table)?, num_blocks);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_and_open_partitioned_index() -> Result<()> {
+        let dir = tempdir()?;
+        let flat = build_table(0, &dir.path().join("1.sst"))?;
+        let num_blocks = check_table(&flat)?;
+
+        let path = dir.path().join("2.sst");
+        let table = build_table(256, &path)?;
+        assert_eq!(table.meta.index_type, IndexType::Partitioned);
+        assert!(table.num_index_partitions() > 1);
+        assert_eq!(check_table(&table)?, num_blocks);
+
+        // only the top-level index is loaded when opening the table,
+        // partitions are loaded through the block cache
+        let block_cache = Arc::new(BlockCache::new(4096));
+        let table = SsTable::open(
+            2,
+            Some(block_cache.clone()),
+            FileObject::open(&PosixFileSystem, &path, FileOptions::default())?,
+        )?;
+        assert_eq!(table.meta.index_type, IndexType::Partitioned);
+        assert_eq!(
+            table.meta.block_meta_vec.len(),
+            table.num_index_partitions()
+        );
+        assert!(block_cache.is_empty());
+        assert_eq!(check_table(&table)?, num_blocks);
+        assert_eq!(block_cache.len(), num_blocks + table.num_index_partitions());
+
+        Ok(())
+    }
+
+    // count keys with the given prefix
+    struct PrefixCounter {
+        prefix: &'static [u8],
+        count: u64,
+    }
+
+    impl TablePropertiesCollector for PrefixCounter {
+        fn add(&mut self, key: KeySlice, _value: &[u8]) {
+            if key.key_ref().starts_with(self.prefix) {
+                self.count += 1;
+            }
+        }
+
+        fn finish(&mut self) -> BTreeMap<String, Vec<u8>> {
+            BTreeMap::from([(
+                "prefix_count".to_string(),
+                self.count.to_be_bytes().to_vec(),
+            )])
+        }
+    }
+
+    #[test]
+    fn test_table_properties() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("1.sst");
+        let mut builder = SsTableBuilder::create(128)?;
+        builder.add_properties_collector(Box::new(PrefixCounter {
+            prefix: b"key_0000",
+            count: 0,
+        }));
+        for i in 0..100 {
+            // every tenth key is a tombstone
+            let value = if i % 10 == 0 {
+                String::new()
+            } else {
+                format!("value_{}", i)
+            };
+            builder.add(key_of(i + 5).to_key_slice(), value.as_bytes())?;
+        }
+        let table = builder.build(1, None, &path)?;
+        let properties = table.properties().clone();
+        assert_eq!(properties.num_entries, 100);
+        assert_eq!(properties.num_tombstones, 10);
+        assert_eq!(properties.min_version, 5);
+        assert_eq!(properties.max_version, 104);
+        assert!(properties.creation_time > 0);
+        assert_eq!(
+            properties.user_collected.get("prefix_count"),
+            Some(&5u64.to_be_bytes().to_vec())
+        );
+
+        let table = SsTable::open(
+            1,
+            None,
+            FileObject::open(&PosixFileSystem, &path, FileOptions::default())?,
+        )?;
+        assert_eq!(table.properties(), &properties);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_of_many_keys() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("1.sst");
+        let mut builder = SsTableBuilder::create(4096)?;
+        for i in 0..20000 {
+            builder.add(key_of(i).to_key_slice(), b"value")?;
+        }
+        let table = builder.build(1, None, &path)?;
+        for i in 0..20000 {
+            assert!(table.may_contain(key_of(i).key_ref()));
+        }
+
+        let table = SsTable::open(
+            1,
+            None,
+            FileObject::open(&PosixFileSystem, &path, FileOptions::default())?,
+        )?;
+        for i in 0..20000 {
+            assert!(table.may_contain(key_of(i).key_ref()));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_of_hot_key() -> Result<()> {
+        let dir = tempdir()?;
+        let mut builder = SsTableBuilder::create(4096)?;
+        // versions of one key are ordered from old to new
+        for version in 1..=1000 {
+            let key = KeyBytes::new(Bytes::from("hot_key"), version);
+            builder.add(key.to_key_slice(), b"value")?;
+        }
+        let table = builder.build(1, None, dir.path().join("1.sst"))?;
+        assert!(table.may_contain(b"hot_key"));
+        assert_eq!(table.properties().num_entries, 1000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_crash() -> Result<()> {
+        let dir = std::path::Path::new("/db");
+        let path = dir.join("1.sst");
+        run_crash_test(
+            |fs, acknowledged| {
+                fs.create_dir_all(dir)?;
+                let mut builder = SsTableBuilder::create(128)?;
+                builder.set_file_system(fs.clone());
+                for i in 0..1000 {
+                    let value = format!("value_{}", i);
+                    builder.add(key_of(i).to_key_slice(), value.as_bytes())?;
+                }
+                builder.build(1, None, &path)?;
+                fs.sync_dir(dir)?;
+                acknowledged.push(1);
+                Ok(())
+            },
+            |fs, acknowledged: &[usize]| {
+                // a table is either built completely or not found at all
+                if acknowledged.is_empty() {
+                    assert!(!fs.exists(&path));
+                    return Ok(());
+                }
+                let table = SsTable::open(
+                    1,
+                    None,
+                    FileObject::open(fs.as_ref(), &path, FileOptions::default())?,
+                )?;
+                check_table(&table)?;
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_read_fault() -> Result<()> {
+        let fs = Arc::new(FaultInjectionFileSystem::new(
+            Arc::new(MemFileSystem::new()),
+        ));
+        let path = std::path::Path::new("/1.sst");
+        let mut builder = SsTableBuilder::create(128)?;
+        builder.set_file_system(fs.clone());
+        for i in 0..1000 {
+            builder.add(key_of(i).to_key_slice(), b"value")?;
+        }
+        let table = builder.build(1, None, path)?;
+
+        // broken reads are reported as errors
+        fs.set_read_fault(ReadFault::ShortRead);
+        assert!(table.read_block(0, 0).is_err());
+        fs.set_read_fault(ReadFault::Corruption);
+        assert!(table.read_block(0, 0).is_err());
+        fs.set_read_fault(ReadFault::None);
+        table.read_block(0, 0)?;
+
+        Ok(())
+    }
+}
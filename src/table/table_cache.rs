@@ -0,0 +1,140 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use anyhow::Result;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use super::SsTable;
+use super::SsTableId;
+
+// LRU cache of opened sstables, which bounds the number of open files.
+// An opened sstable keeps the file descriptor together with the parsed
+// index and filter, all of them are released when it is evicted.
+// Readers holding an evicted table can still use it until they drop it.
+pub struct TableCache {
+    cache: Mutex<LruCache<SsTableId, Arc<SsTable>>>,
+}
+
+impl TableCache {
+    // 0 means the number of opened sstables is unlimited
+    pub fn new(capacity: usize) -> Self {
+        let cache = match NonZeroUsize::new(capacity) {
+            Some(capacity) => LruCache::new(capacity),
+            None => LruCache::unbounded(),
+        };
+        Self {
+            cache: Mutex::new(cache),
+        }
+    }
+
+    pub fn get(&self, id: SsTableId) -> Option<Arc<SsTable>> {
+        self.cache.lock().get(&id).cloned()
+    }
+
+    pub fn insert(&self, table: Arc<SsTable>) {
+        self.cache.lock().put(table.id(), table);
+    }
+
+    // return the opened table, or open it with `open` and insert it into the cache.
+    // the lock is not held while opening, so concurrent readers may open the same table.
+    pub fn get_or_open<F>(&self, id: SsTableId, open: F) -> Result<Arc<SsTable>>
+    where F: FnOnce() -> Result<SsTable> {
+        if let Some(table) = self.get(id) {
+            return Ok(table);
+        }
+        let table = Arc::new(open()?);
+        self.insert(table.clone());
+        Ok(table)
+    }
+
+    // remove the table from cache, called when the table is deleted
+    pub fn evict(&self, id: SsTableId) {
+        self.cache.lock().pop(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.lock().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use super::TableCache;
+    use crate::base::KeyBytes;
+    use crate::fs::FileOptions;
+    use crate::fs::PosixFileSystem;
+    use crate::table::FileObject;
+    use crate::table::SsTable;
+    use crate::table::SsTableBuilder;
+
+    #[test]
+    fn test_table_cache() -> Result<()> {
+        let dir = tempdir()?;
+        let path_of = |id: u64| dir.path().join(format!("{}.sst", id));
+        for id in 0..4 {
+            let mut builder = SsTableBuilder::create(128)?;
+            let key = KeyBytes::new(Bytes::from(format!("key_{}", id)), id);
+            builder.add(key.to_key_slice(), b"value")?;
+            builder.build(id, None, path_of(id))?;
+        }
+        let open = |id: u64| {
+            SsTable::open(
+                id,
+                None,
+                FileObject::open(&PosixFileSystem, &path_of(id), FileOptions::default())?,
+            )
+        };
+
+        let table_cache = TableCache::new(2);
+        let table_0 = table_cache.get_or_open(0, || open(0))?;
+        table_cache.get_or_open(1, || open(1))?;
+        assert_eq!(table_cache.len(), 2);
+
+        // table 0 is the least recently used one
+        table_cache.get_or_open(2, || open(2))?;
+        assert_eq!(table_cache.len(), 2);
+        assert!(table_cache.get(0).is_none());
+        assert!(table_cache.get(1).is_some());
+
+        // the evicted table is still usable by its holder
+        assert_eq!(table_0.first_key().key_ref(), b"key_0");
+        let reopened = table_cache.get_or_open(0, || open(0))?;
+        assert!(!Arc::ptr_eq(&table_0, &reopened));
+
+        table_cache.evict(0);
+        assert!(table_cache.get(0).is_none());
+        assert!(table_cache.get_or_open(5, || open(5)).is_err());
+
+        let table_cache = TableCache::new(0);
+        for id in 0..4 {
+            table_cache.get_or_open(id, || open(id))?;
+        }
+        assert_eq!(table_cache.len(), 4);
+        Ok(())
+    }
+}
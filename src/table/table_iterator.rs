@@ -0,0 +1,430 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::BlockMetaVec;
+use super::SsTable;
+use crate::base::KeySlice;
+use crate::base::KeyVec;
+use crate::base::VERSION_DEFAULT;
+use crate::base::Version;
+use crate::block::Block;
+use crate::block::BlockIterator;
+use crate::iterator::StorageIterator;
+
+// Iterator over a sstable, only keys with version in [min_version, max_version] are returned.
+// Blocks and index partitions out of the version range are skipped without being read.
+pub struct SsTableIterator {
+    table: Arc<SsTable>,
+
+    min_version: Version,
+    max_version: Version,
+
+    // see `SsTable::global_version`
+    global_version: Option<Version>,
+    // key with the global version
+    key: KeyVec,
+
+    partition: Arc<BlockMetaVec>,
+    partition_idx: usize,
+    block_idx: usize,
+
+    // None means the iterator is invalid
+    block_iter: Option<BlockIterator>,
+
+    // number of blocks read at once when moving to a block not prefetched,
+    // 0 or 1 means no readahead
+    readahead: usize,
+    // blocks read ahead, (partition index, block index, block)
+    prefetched: VecDeque<(usize, usize, Arc<Block>)>,
+}
+
+impl SsTableIterator {
+    fn new(table: Arc<SsTable>, min_version: Version, max_version: Version) -> Self {
+        let partition = table.meta.block_meta_vec.clone();
+        let global_version = table.global_version();
+        Self {
+            table,
+            min_version,
+            max_version,
+            global_version,
+            key: KeyVec::new(),
+            partition,
+            partition_idx: 0,
+            block_idx: 0,
+            block_iter: None,
+            readahead: 0,
+            prefetched: VecDeque::new(),
+        }
+    }
+
+    // read `num_blocks` blocks with one batched read when moving to the next block,
+    // useful for scans over many blocks
+    pub fn set_readahead(&mut self, num_blocks: usize) {
+        self.readahead = num_blocks;
+        self.prefetched.clear();
+    }
+
+    pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
+        Self::create_and_seek_to_first_with_versions(table, VERSION_DEFAULT, Version::MAX)
+    }
+
+    pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_to_key_with_versions(table, key, VERSION_DEFAULT, Version::MAX)
+    }
+
+    pub fn create_and_seek_to_first_with_versions(
+        table: Arc<SsTable>,
+        min_version: Version,
+        max_version: Version,
+    ) -> Result<Self> {
+        let mut iter = Self::new(table, min_version, max_version);
+        iter.seek_to_first()?;
+        Ok(iter)
+    }
+
+    pub fn create_and_seek_to_key_with_versions(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        min_version: Version,
+        max_version: Version,
+    ) -> Result<Self> {
+        let mut iter = Self::new(table, min_version, max_version);
+        iter.seek_to_key(key)?;
+        Ok(iter)
+    }
+
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        self.block_iter = None;
+        if !self
+            .table
+            .overlaps_versions(self.min_version, self.max_version)
+        {
+            return Ok(());
+        }
+        self.seek_to_partition(0)?;
+        self.seek_to_block(0)?;
+        self.skip_out_of_range_keys()
+    }
+
+    // seek to the first key >= `key`
+    pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        self.block_iter = None;
+        // keys are saved with the default version in tables with a global version
+        let key = match self.global_version {
+            Some(global_version) if key.version() <= global_version => {
+                KeySlice::from_slice(key.key_ref(), VERSION_DEFAULT)
+            }
+            Some(_) => KeySlice::from_slice(key.key_ref(), Version::MAX),
+            None => key,
+        };
+        if !self
+            .table
+            .overlaps_versions(self.min_version, self.max_version)
+        {
+            return Ok(());
+        }
+        let Some((partition_idx, block_idx)) = self.table.find_block(key)? else {
+            return Ok(());
+        };
+        self.partition = self.table.read_index_partition(partition_idx)?;
+        self.partition_idx = partition_idx;
+        self.seek_to_block(block_idx)?;
+        if let Some(block_iter) = &mut self.block_iter {
+            block_iter.seek_to_key(key);
+        }
+        self.skip_out_of_range_keys()
+    }
+
+    // load the first partition starting from `partition_idx` that overlaps the version range
+    fn seek_to_partition(&mut self, mut partition_idx: usize) -> Result<bool> {
+        let num_partitions = self.table.num_index_partitions();
+        while partition_idx < num_partitions
+            && !self.table.partition_overlaps_versions(
+                partition_idx,
+                self.min_version,
+                self.max_version,
+            )
+        {
+            partition_idx += 1;
+        }
+        if partition_idx >= num_partitions {
+            self.block_iter = None;
+            return Ok(false);
+        }
+        self.partition = self.table.read_index_partition(partition_idx)?;
+        self.partition_idx = partition_idx;
+        Ok(true)
+    }
+
+    // load the first block starting from `block_idx` of the current partition
+    // that overlaps the version range, move to the next partitions if there is none
+    fn seek_to_block(&mut self, mut block_idx: usize) -> Result<()> {
+        loop {
+            while block_idx < self.partition.len()
+                && self.global_version.is_none()
+                && !self
+                    .partition
+                    .get(block_idx)
+                    .overlaps_versions(self.min_version, self.max_version)
+            {
+                block_idx += 1;
+            }
+            if block_idx < self.partition.len() {
+                let block = self.load_block(block_idx)?;
+                self.block_idx = block_idx;
+                self.block_iter = Some(BlockIterator::create_and_seek_to_first(block));
+                return Ok(());
+            }
+            if !self.seek_to_partition(self.partition_idx + 1)? {
+                return Ok(());
+            }
+            block_idx = 0;
+        }
+    }
+
+    // load the block of the current partition, read ahead the following blocks
+    // overlapping the version range if readahead is enabled
+    fn load_block(&mut self, block_idx: usize) -> Result<Arc<Block>> {
+        if self.readahead <= 1 {
+            return self.table.read_block_in_partition(
+                &self.partition,
+                self.partition_idx,
+                block_idx,
+            );
+        }
+
+        let target = (self.partition_idx, block_idx);
+        while let Some((partition_idx, block_idx, _)) = self.prefetched.front() {
+            if (*partition_idx, *block_idx) >= target {
+                break;
+            }
+            self.prefetched.pop_front();
+        }
+        if !matches!(self.prefetched.front(), Some((p, b, _)) if (*p, *b) == target) {
+            let blocks: Vec<(usize, usize)> = (block_idx..self.partition.len())
+                .filter(|idx| {
+                    self.global_version.is_some()
+                        || self
+                            .partition
+                            .get(*idx)
+                            .overlaps_versions(self.min_version, self.max_version)
+                })
+                .take(self.readahead)
+                .map(|idx| (self.partition_idx, idx))
+                .collect();
+            let loaded = self.table.read_blocks(&blocks)?;
+            self.prefetched = blocks
+                .into_iter()
+                .zip(loaded)
+                .map(|((partition_idx, block_idx), block)| (partition_idx, block_idx, block))
+                .collect();
+        }
+        // safe to unwrap, the target block is the first prefetched one
+        Ok(self.prefetched.pop_front().unwrap().2)
+    }
+
+    fn skip_out_of_range_keys(&mut self) -> Result<()> {
+        while let Some(block_iter) = &mut self.block_iter {
+            if !block_iter.is_valid() {
+                self.seek_to_block(self.block_idx + 1)?;
+                continue;
+            }
+            let version = self
+                .global_version
+                .unwrap_or_else(|| block_iter.key().version());
+            if version >= self.min_version && version <= self.max_version {
+                if self.global_version.is_some() {
+                    self.key = KeyVec::from_vec(block_iter.key().key_ref().to_vec(), version);
+                }
+                break;
+            }
+            block_iter.next();
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for SsTableIterator {
+    fn key(&self) -> KeySlice<'_> {
+        if self.global_version.is_some() {
+            return self.key.to_key_slice();
+        }
+        // safe to unwrap, callers must check `is_valid` first
+        self.block_iter.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.block_iter.as_ref().unwrap().value()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.block_iter
+            .as_ref()
+            .is_some_and(|block_iter| block_iter.is_valid())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if let Some(block_iter) = &mut self.block_iter {
+            block_iter.next();
+        }
+        self.skip_out_of_range_keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use super::SsTableIterator;
+    use crate::base::KeyBytes;
+    use crate::iterator::StorageIterator;
+    use crate::table::BlockCache;
+    use crate::table::SsTable;
+    use crate::table::SsTableBuilder;
+
+    fn key_of(i: usize) -> KeyBytes {
+        KeyBytes::new(Bytes::from(format!("key_{:05}", i)), i as u64)
+    }
+
+    fn build_table(
+        index_partition_size: usize,
+        block_cache: Arc<BlockCache>,
+        path: &std::path::Path,
+    ) -> Result<Arc<SsTable>> {
+        let mut builder = SsTableBuilder::create(128)?;
+        builder.set_index_partition_size(index_partition_size);
+        for i in 0..1000 {
+            let value = format!("value_{}", i);
+            builder.add(key_of(i).to_key_slice(), value.as_bytes())?;
+        }
+        Ok(Arc::new(builder.build(1, Some(block_cache), path)?))
+    }
+
+    fn collect(mut iter: SsTableIterator) -> Result<Vec<usize>> {
+        let mut versions = Vec::new();
+        while iter.is_valid() {
+            let version = iter.key().version() as usize;
+            assert_eq!(iter.key(), key_of(version).to_key_slice());
+            assert_eq!(iter.value(), format!("value_{}", version).as_bytes());
+            versions.push(version);
+            iter.next()?;
+        }
+        Ok(versions)
+    }
+
+    fn test_version_range(index_partition_size: usize) -> Result<()> {
+        let dir = tempdir()?;
+        let block_cache = Arc::new(BlockCache::new(4096));
+        let table = build_table(
+            index_partition_size,
+            block_cache.clone(),
+            &dir.path().join("1.sst"),
+        )?;
+        assert_eq!(table.min_version(), 0);
+        assert_eq!(table.max_version(), 999);
+
+        let iter = SsTableIterator::create_and_seek_to_first(table.clone())?;
+        assert_eq!(collect(iter)?, (0..1000).collect::<Vec<_>>());
+        let num_cached = block_cache.len();
+
+        // changes since version 900 only read the last blocks
+        let block_cache = Arc::new(BlockCache::new(4096));
+        let table = build_table(
+            index_partition_size,
+            block_cache.clone(),
+            &dir.path().join("2.sst"),
+        )?;
+        let iter =
+            SsTableIterator::create_and_seek_to_first_with_versions(table.clone(), 901, u64::MAX)?;
+        assert_eq!(collect(iter)?, (901..1000).collect::<Vec<_>>());
+        assert!(block_cache.len() * 5 < num_cached);
+
+        // read at an old version
+        let iter = SsTableIterator::create_and_seek_to_first_with_versions(table.clone(), 0, 100)?;
+        assert_eq!(collect(iter)?, (0..=100).collect::<Vec<_>>());
+        let iter = SsTableIterator::create_and_seek_to_key_with_versions(
+            table.clone(),
+            key_of(50).to_key_slice(),
+            0,
+            100,
+        )?;
+        assert_eq!(collect(iter)?, (50..=100).collect::<Vec<_>>());
+        assert!(block_cache.len() * 2 < num_cached);
+
+        // the whole table is skipped
+        assert!(!table.overlaps_versions(1000, u64::MAX));
+        let iter =
+            SsTableIterator::create_and_seek_to_first_with_versions(table.clone(), 1000, u64::MAX)?;
+        assert!(!iter.is_valid());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterate_flat_index() -> Result<()> {
+        test_version_range(0)
+    }
+
+    #[test]
+    fn test_iterate_partitioned_index() -> Result<()> {
+        test_version_range(256)
+    }
+
+    #[test]
+    fn test_seek_to_key() -> Result<()> {
+        let dir = tempdir()?;
+        let block_cache = Arc::new(BlockCache::new(4096));
+        let table = build_table(256, block_cache, &dir.path().join("1.sst"))?;
+        for i in [0, 1, 99, 500, 998, 999] {
+            let iter =
+                SsTableIterator::create_and_seek_to_key(table.clone(), key_of(i).to_key_slice())?;
+            assert_eq!(collect(iter)?, (i..1000).collect::<Vec<_>>());
+        }
+        let iter = SsTableIterator::create_and_seek_to_key(table, key_of(1000).to_key_slice())?;
+        assert!(!iter.is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_readahead() -> Result<()> {
+        let dir = tempdir()?;
+        for index_partition_size in [0, 256] {
+            let block_cache = Arc::new(BlockCache::new(4096));
+            let path = dir.path().join(format!("{}.sst", index_partition_size));
+            let table = build_table(index_partition_size, block_cache.clone(), &path)?;
+            for readahead in [2, 8, 1000] {
+                let mut iter = SsTableIterator::create_and_seek_to_first(table.clone())?;
+                iter.set_readahead(readahead);
+                iter.seek_to_first()?;
+                assert_eq!(collect(iter)?, (0..1000).collect::<Vec<_>>());
+
+                let mut iter =
+                    SsTableIterator::create_and_seek_to_first_with_versions(table.clone(), 0, 100)?;
+                iter.set_readahead(readahead);
+                iter.seek_to_key(key_of(50).to_key_slice())?;
+                assert_eq!(collect(iter)?, (50..=100).collect::<Vec<_>>());
+            }
+        }
+        Ok(())
+    }
+}
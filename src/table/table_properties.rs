@@ -0,0 +1,183 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use anyhow::bail;
+use bytes::Buf;
+use bytes::BufMut;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::base::KeySlice;
+use crate::base::VERSION_DEFAULT;
+use crate::base::Version;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterType {
+    Cuckoo,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionType {
+    None,
+}
+
+// Statistics of a sstable, saved in the properties block when building the table
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TableProperties {
+    // number of key-value pairs, including tombstones
+    pub num_entries: u64,
+    // number of deletions(empty values)
+    pub num_tombstones: u64,
+    // total size of keys(with version) and values before encoding
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+
+    pub min_version: Version,
+    pub max_version: Version,
+
+    pub filter_type: FilterType,
+    pub compression_type: CompressionType,
+
+    // seconds since unix epoch
+    pub creation_time: u64,
+
+    // properties added by `TablePropertiesCollector`s, keyed by property name
+    pub user_collected: BTreeMap<String, Vec<u8>>,
+}
+
+impl TableProperties {
+    pub fn new() -> Self {
+        Self {
+            num_entries: 0,
+            num_tombstones: 0,
+            raw_key_size: 0,
+            raw_value_size: 0,
+            min_version: Version::MAX,
+            max_version: VERSION_DEFAULT,
+            filter_type: FilterType::Cuckoo,
+            compression_type: CompressionType::None,
+            creation_time: 0,
+            user_collected: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.num_entries += 1;
+        if value.is_empty() {
+            self.num_tombstones += 1;
+        }
+        self.raw_key_size += key.raw_len() as u64;
+        self.raw_value_size += value.len() as u64;
+        self.min_version = std::cmp::min(self.min_version, key.version());
+        self.max_version = std::cmp::max(self.max_version, key.version());
+    }
+
+    pub fn set_creation_time_now(&mut self) {
+        self.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+    }
+
+    // properties block format:
+    // crc32 of bincode(properties) + bincode(properties)
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let data = bincode::serialize(self)?;
+        buf.put_u32(crc32fast::hash(&data));
+        buf.extend(data);
+        Ok(())
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.remaining() < std::mem::size_of::<u32>() {
+            bail!("TableProperties is too short");
+        }
+        let checksum = buf.get_u32();
+        if checksum != crc32fast::hash(buf) {
+            bail!("TableProperties checksum mismatched");
+        }
+        Ok(bincode::deserialize(buf)?)
+    }
+}
+
+impl Default for TableProperties {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Collects user defined properties when building a sstable, the collected
+// properties are saved into `TableProperties::user_collected`.
+pub trait TablePropertiesCollector: Send {
+    // called for every key-value pair added into the table
+    fn add(&mut self, key: KeySlice, value: &[u8]);
+
+    // called once when the table is finished, return the collected properties
+    fn finish(&mut self) -> BTreeMap<String, Vec<u8>>;
+}
+
+// Creates a new collector for every sstable built by the engine
+pub trait TablePropertiesCollectorFactory: Send + Sync {
+    // identifies the factory in a `CompactionJob`, a compaction worker creates the
+    // collectors with its own factory of the same name
+    fn name(&self) -> &str;
+
+    fn create(&self) -> Box<dyn TablePropertiesCollector>;
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    use super::TableProperties;
+    use crate::base::KeyBytes;
+
+    #[test]
+    fn test_encode_decode_table_properties() -> Result<()> {
+        let mut properties = TableProperties::new();
+        properties.add(
+            KeyBytes::new(Bytes::from("hello"), 3).to_key_slice(),
+            b"world",
+        );
+        properties.add(KeyBytes::new(Bytes::from("test"), 7).to_key_slice(), b"");
+        properties.set_creation_time_now();
+        properties
+            .user_collected
+            .insert("name".to_string(), b"value".to_vec());
+
+        assert_eq!(properties.num_entries, 2);
+        assert_eq!(properties.num_tombstones, 1);
+        assert_eq!(properties.raw_key_size, 9 + 8 * 2);
+        assert_eq!(properties.raw_value_size, 5);
+        assert_eq!(properties.min_version, 3);
+        assert_eq!(properties.max_version, 7);
+
+        let mut buf = Vec::new();
+        properties.encode(&mut buf)?;
+        assert_eq!(properties, TableProperties::decode(&buf)?);
+
+        // corrupt the properties block
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        assert!(TableProperties::decode(&buf).is_err());
+
+        Ok(())
+    }
+}
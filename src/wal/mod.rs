@@ -14,3 +14,5 @@
 
 #[allow(clippy::module_inception)]
 mod wal;
+
+pub use wal::Wal;
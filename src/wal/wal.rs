@@ -12,39 +12,67 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::BufWriter;
-use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
 
+use anyhow::Context;
 use anyhow::Result;
+use anyhow::bail;
+use bytes::Buf;
 use bytes::BufMut;
+use bytes::Bytes;
 use parking_lot::Mutex;
 
+use crate::base::KeyBytes;
 use crate::base::KeySlice;
+use crate::fs::FileOptions;
+use crate::fs::FileSystem;
+use crate::fs::WritableFile;
 
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    file: Arc<Mutex<Box<dyn WritableFile>>>,
     path: String,
 }
 
 impl Wal {
-    pub fn create(path: impl AsRef<Path>) -> Result<Wal> {
-        let wal_path = path.as_ref().to_string_lossy().into_owned();
+    pub fn create(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Wal> {
+        let path = path.as_ref();
+        let wal_path = path.to_string_lossy().into_owned();
+        if fs.exists(path) {
+            bail!("wal {} already exists", wal_path);
+        }
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .create_new(true)
-                    .write(true)
-                    .read(true)
-                    .open(path)?,
-            ))),
+            file: Arc::new(Mutex::new(fs.create(path, FileOptions::default())?)),
             path: wal_path,
         })
     }
 
+    // Read back the entries in the wal. A batch partially written at the tail
+    // is not acknowledged, so it is ignored.
+    pub fn recover(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Vec<(KeyBytes, Bytes)>> {
+        let path = path.as_ref();
+        let mut buf = fs.read_all(path)?;
+        let mut entries = Vec::new();
+        while buf.len() >= 4 {
+            let len = (&buf[..4]).get_u32() as usize;
+            if buf.len() < len + 8 {
+                break;
+            }
+            buf.advance(4);
+            let mut batch = buf.split_to(len);
+            if buf.get_u32() != crc32fast::hash(&batch) {
+                if buf.is_empty() {
+                    break;
+                }
+                bail!("wal {:?} has a corrupted batch", path);
+            }
+            while batch.has_remaining() {
+                entries.push(Self::read_record(&mut batch));
+            }
+        }
+        Ok(entries)
+    }
+
     pub fn write(&self, key: KeySlice, value: &[u8]) -> Result<()> {
         self.write_batch(&[(key, value)])
     }
@@ -55,10 +83,20 @@ impl Wal {
         for (key, value) in data {
             Self::write_record(&mut buf, key, value);
         }
-        let path = &self.path;
-        file.write_all(&(buf.len() as u32).to_be_bytes())?;
-        file.write_all(&buf)?;
-        file.write_all(&crc32fast::hash(&buf).to_be_bytes())?;
+        let mut record = Vec::with_capacity(buf.len() + 8);
+        record.put_u32(buf.len() as u32);
+        record.put_slice(&buf);
+        record.put_u32(crc32fast::hash(&buf));
+        file.append(&record)
+            .with_context(|| format!("write to wal {}", self.path))?;
+        Ok(())
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.file
+            .lock()
+            .sync()
+            .with_context(|| format!("sync wal {}", self.path))?;
         Ok(())
     }
 
@@ -69,4 +107,66 @@ impl Wal {
         buf.put_u16(value.len() as u16);
         buf.put_slice(value);
     }
+
+    fn read_record(buf: &mut Bytes) -> (KeyBytes, Bytes) {
+        let key_len = buf.get_u16() as usize;
+        let key = buf.copy_to_bytes(key_len);
+        let version = buf.get_u64();
+        let value_len = buf.get_u16() as usize;
+        let value = buf.copy_to_bytes(value_len);
+        (KeyBytes::new(key, version), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    use super::Wal;
+    use crate::base::KeyBytes;
+    use crate::base::KeySlice;
+    use crate::fs::FileSystem;
+    use crate::fs::run_crash_test;
+
+    #[test]
+    fn test_wal_crash() -> Result<()> {
+        let dir = Path::new("/db");
+        let path = dir.join("00000.wal");
+        run_crash_test(
+            |fs, acknowledged| {
+                fs.create_dir_all(dir)?;
+                let wal = Wal::create(fs.as_ref(), &path)?;
+                fs.sync_dir(dir)?;
+                for i in 0..10u64 {
+                    let keys: Vec<_> = (0..3).map(|j| format!("key_{}_{}", i, j)).collect();
+                    let batch: Vec<_> = keys
+                        .iter()
+                        .map(|key| (KeySlice::from_slice(key.as_bytes(), i), key.as_bytes()))
+                        .collect();
+                    wal.write_batch(&batch)?;
+                    wal.sync()?;
+                    acknowledged.extend(
+                        batch.iter().map(|(key, value)| {
+                            (key.to_key_bytes(), Bytes::copy_from_slice(value))
+                        }),
+                    );
+                }
+                Ok(())
+            },
+            |fs, acknowledged: &[(KeyBytes, Bytes)]| {
+                if !fs.exists(&path) {
+                    assert!(acknowledged.is_empty());
+                    return Ok(());
+                }
+                // only a batch failed in sync may be written in addition
+                let entries = Wal::recover(fs.as_ref(), &path)?;
+                assert_eq!(&entries[..acknowledged.len()], acknowledged);
+                assert!(entries.len() - acknowledged.len() <= 3);
+                Ok(())
+            },
+        )
+    }
 }
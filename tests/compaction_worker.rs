@@ -0,0 +1,235 @@
+// Copyright (c) 2025 Lichuang(codedump)
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use lsm_engine::base::KeySlice;
+use lsm_engine::base::Version;
+use lsm_engine::compact::CompactionDecision;
+use lsm_engine::compact::CompactionFilter;
+use lsm_engine::compact::CompactionJob;
+use lsm_engine::compact::CompactionJobInput;
+use lsm_engine::compact::CompactionTask;
+use lsm_engine::compact::run_compaction_job;
+use lsm_engine::engine::LsmEngine;
+use lsm_engine::engine::LsmOptions;
+use lsm_engine::fs::FileOptions;
+use lsm_engine::fs::PosixFileSystem;
+use lsm_engine::fs::RateLimiter;
+use lsm_engine::table::FileObject;
+use lsm_engine::table::SsTable;
+use lsm_engine::table::TablePropertiesCollector;
+use lsm_engine::table::TablePropertiesCollectorFactory;
+use tempfile::tempdir;
+
+fn open(path: &Path, compaction_worker: Option<PathBuf>) -> Result<LsmEngine> {
+    let options = LsmOptions {
+        block_size: 128,
+        target_file_size: 4096,
+        compaction_worker,
+        // the worker writes at the same rate
+        rate_limiter: Some(Arc::new(RateLimiter::new(64 << 20))),
+        ..LsmOptions::default()
+    };
+    LsmEngine::open(path, options)
+}
+
+fn write(engine: &LsmEngine) -> Result<()> {
+    for round in 0..3 {
+        for i in (round * 100..600).step_by(round + 1) {
+            let value = format!("value_{}_{}", round, i);
+            engine.put(format!("key_{:05}", i).as_bytes(), value.as_bytes())?;
+        }
+        engine.delete(format!("key_{:05}", round * 13).as_bytes())?;
+        engine.force_flush()?;
+    }
+    Ok(())
+}
+
+fn read(engine: &LsmEngine) -> Result<Vec<Option<Bytes>>> {
+    (0..610)
+        .map(|i| engine.get(format!("key_{:05}", i).as_bytes()))
+        .collect()
+}
+
+fn file_names(path: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in path.read_dir()? {
+        names.push(entry?.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[test]
+fn test_compaction_worker() -> Result<()> {
+    let local_dir = tempdir()?;
+    let local = open(local_dir.path(), None)?;
+    write(&local)?;
+    local.force_full_compaction()?;
+
+    let dir = tempdir()?;
+    let worker = PathBuf::from(env!("CARGO_BIN_EXE_compaction_worker"));
+    let engine = open(dir.path(), Some(worker))?;
+    write(&engine)?;
+    let before = file_names(dir.path())?;
+    engine.force_full_compaction()?;
+
+    // the outputs of the worker are the same as compacting in the engine
+    assert_eq!(read(&engine)?, read(&local)?);
+    // the inputs are replaced by the outputs, which are renamed after new ids
+    let after = file_names(dir.path())?;
+    let sst_files = |names: &[String]| -> Vec<String> {
+        names
+            .iter()
+            .filter(|name| name.ends_with(".sst"))
+            .cloned()
+            .collect()
+    };
+    assert!(after.iter().all(|name| !name.starts_with("job_")));
+    assert!(sst_files(&after).iter().all(|name| !before.contains(name)));
+    let num_outputs = sst_files(&after).len();
+    assert_eq!(num_outputs, sst_files(&file_names(local_dir.path())?).len());
+    assert!(num_outputs > 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_compaction_worker_failure() -> Result<()> {
+    let dir = tempdir()?;
+    let engine = open(dir.path(), Some(dir.path().join("no_such_worker")))?;
+    write(&engine)?;
+    let expected = read(&engine)?;
+    let before = file_names(dir.path())?;
+
+    // the engine is unchanged if the worker fails
+    assert!(engine.force_full_compaction().is_err());
+    assert_eq!(file_names(dir.path())?, before);
+    assert_eq!(read(&engine)?, expected);
+
+    Ok(())
+}
+
+struct KeepAll;
+
+impl CompactionFilter for KeepAll {
+    fn filter(&self, _: usize, _: &[u8], _: Version, _: &[u8]) -> CompactionDecision {
+        CompactionDecision::Keep
+    }
+}
+
+#[test]
+fn test_compaction_worker_with_filter() -> Result<()> {
+    let dir = tempdir()?;
+    let options = LsmOptions {
+        compaction_worker: Some(PathBuf::from(env!("CARGO_BIN_EXE_compaction_worker"))),
+        compaction_filter: Some(Arc::new(KeepAll)),
+        ..LsmOptions::default()
+    };
+    // the worker can not run the filter of the engine
+    assert!(LsmEngine::open(dir.path(), options).is_err());
+    Ok(())
+}
+
+// counts the entries of a sstable
+struct EntryCounter(u64);
+
+impl TablePropertiesCollector for EntryCounter {
+    fn add(&mut self, _key: KeySlice, _value: &[u8]) {
+        self.0 += 1;
+    }
+
+    fn finish(&mut self) -> BTreeMap<String, Vec<u8>> {
+        BTreeMap::from([("entries".to_string(), self.0.to_be_bytes().to_vec())])
+    }
+}
+
+struct EntryCounterFactory;
+
+impl TablePropertiesCollectorFactory for EntryCounterFactory {
+    fn name(&self) -> &str {
+        "entry_counter"
+    }
+
+    fn create(&self) -> Box<dyn TablePropertiesCollector> {
+        Box::new(EntryCounter(0))
+    }
+}
+
+#[test]
+fn test_compaction_worker_collectors() -> Result<()> {
+    let dir = tempdir()?;
+    let options = LsmOptions {
+        block_size: 128,
+        compaction_worker: Some(PathBuf::from(env!("CARGO_BIN_EXE_compaction_worker"))),
+        table_properties_collector_factories: vec![Arc::new(EntryCounterFactory)],
+        ..LsmOptions::default()
+    };
+    let engine = LsmEngine::open(dir.path(), options)?;
+    write(&engine)?;
+    let before = file_names(dir.path())?;
+
+    // the worker binary has no collectors, the job fails instead of
+    // writing sstables without the collected properties
+    let err = engine.force_full_compaction().unwrap_err();
+    assert!(format!("{:#}", err).contains("entry_counter"));
+    assert_eq!(file_names(dir.path())?, before);
+
+    // a worker with the factory collects the properties of the outputs
+    let inputs: Vec<CompactionJobInput> = before
+        .iter()
+        .filter(|name| name.ends_with(".sst"))
+        .map(|name| CompactionJobInput {
+            path: dir.path().join(name),
+            global_version: None,
+        })
+        .collect();
+    let output_dir = tempdir()?;
+    let job = CompactionJob {
+        job_id: 1,
+        task: CompactionTask::Full {
+            l0_sstables: (0..inputs.len() as u64).collect(),
+            levels: Vec::new(),
+        },
+        inputs,
+        output_dir: output_dir.path().to_path_buf(),
+        watermark: 0,
+        block_size: 128,
+        index_partition_size: 0,
+        target_file_size: 4096,
+        readahead_blocks: 8,
+        use_direct_io: false,
+        rate_bytes_per_sec: Some(64 << 20),
+        table_properties_collectors: vec!["entry_counter".to_string()],
+    };
+    let factory: Arc<dyn TablePropertiesCollectorFactory> = Arc::new(EntryCounterFactory);
+    let outputs = run_compaction_job(&job, &[factory])?;
+    assert!(!outputs.is_empty());
+    for (idx, output) in outputs.iter().enumerate() {
+        let file = FileObject::open(&PosixFileSystem, &output.path, FileOptions::default())?;
+        let table = SsTable::open(idx as u64, None, file)?;
+        let properties = table.properties();
+        assert_eq!(
+            properties.user_collected.get("entries"),
+            Some(&properties.num_entries.to_be_bytes().to_vec())
+        );
+    }
+    Ok(())
+}